@@ -0,0 +1,126 @@
+//! Window activation tokens, so the file manager comes to the front.
+//!
+//! Wayland compositors only raise a window that presents an activation token.
+//! Browsers pass one to the handler they start (`XDG_ACTIVATION_TOKEN`, or
+//! `DESKTOP_STARTUP_ID` on X11); qmpo forwards it to the file manager.
+//!
+//! Without one, on X11 qmpo starts a startup-notification sequence itself (see
+//! [`x11`](crate::x11)) and uses its ID. On Wayland it does not: Mutter and
+//! KWin only honor a token request made with the focused window's surface and
+//! the serial of the input event, which a handler started in the background
+//! has neither of, so the window opens wherever focus-stealing prevention puts
+//! it.
+//!
+//! A token is used once: [`take`] hands out the pending token a single time.
+
+use std::sync::Mutex;
+
+#[cfg(target_os = "linux")]
+use crate::log::{self, Level};
+#[cfg(target_os = "linux")]
+use crate::x11;
+
+/// Token passed to qmpo and not yet used.
+static PENDING: Mutex<Option<String>> = Mutex::new(None);
+
+/// The startup-notification sequence qmpo started, until it ends.
+#[cfg(target_os = "linux")]
+static SEQUENCE: Mutex<Option<x11::Sequence>> = Mutex::new(None);
+
+/// Remember the token qmpo was started with, if any.
+pub fn init_from_env() {
+    offer(env_token(|name| std::env::var(name).ok()));
+}
+
+/// Start a startup-notification sequence on X11 if qmpo was given no token.
+///
+/// Call before entering the sandbox, which blocks reading the Xauthority file.
+pub fn request() {
+    #[cfg(target_os = "linux")]
+    {
+        let x11 = std::env::var_os("WAYLAND_DISPLAY").is_none_or(|d| d.is_empty())
+            && std::env::var_os("DISPLAY").is_some_and(|d| !d.is_empty());
+        if !x11 || PENDING.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+            return;
+        }
+        match x11::Sequence::start("qmpo") {
+            Ok(sequence) => {
+                offer(Some(sequence.id().to_string()));
+                *SEQUENCE.lock().unwrap_or_else(|e| e.into_inner()) = Some(sequence);
+            }
+            Err(e) => log::log(
+                Level::Debug,
+                "No startup notification",
+                &[("error", &e.to_string())],
+            ),
+        }
+    }
+}
+
+/// End the sequence started by [`request`] if nothing was launched; a
+/// launched program ends it itself once its window appears.
+pub fn finish(launched: bool) {
+    #[cfg(target_os = "linux")]
+    if let Some(sequence) = SEQUENCE.lock().unwrap_or_else(|e| e.into_inner()).take()
+        && !launched
+        && let Err(e) = sequence.remove()
+    {
+        log::log(
+            Level::Debug,
+            "Could not end startup notification",
+            &[("error", &e.to_string())],
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = launched;
+}
+
+/// Replace the pending token (e.g. with one forwarded to `qmpo daemon`).
+pub fn offer(token: Option<String>) {
+    *PENDING.lock().unwrap_or_else(|e| e.into_inner()) = token;
+}
+
+/// Take the pending token for the next launch.
+pub fn take() -> Option<String> {
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// The token from the environment, preferring the Wayland one.
+fn env_token(get: impl Fn(&str) -> Option<String>) -> Option<String> {
+    ["XDG_ACTIVATION_TOKEN", "DESKTOP_STARTUP_ID"]
+        .into_iter()
+        .filter_map(get)
+        .find(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_token() {
+        let env = |pairs: &'static [(&str, &str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            env_token(env(&[
+                ("DESKTOP_STARTUP_ID", "x11"),
+                ("XDG_ACTIVATION_TOKEN", "wl")
+            ])),
+            Some("wl".to_string())
+        );
+        assert_eq!(
+            env_token(env(&[
+                ("XDG_ACTIVATION_TOKEN", ""),
+                ("DESKTOP_STARTUP_ID", "x11")
+            ])),
+            Some("x11".to_string())
+        );
+        assert_eq!(env_token(env(&[])), None);
+    }
+}
@@ -0,0 +1,281 @@
+//! Process setup for launched programs.
+//!
+//! qmpo is started by the browser and inherits its environment, which inside
+//! Snap, Flatpak or AppImage packages points GTK, GIO and the dynamic linker at
+//! the browser's bundled libraries. A file manager started with that
+//! environment may crash or load the wrong modules, so launched programs get a
+//! cleaned environment instead: confinement variables are removed, bundle
+//! directories are dropped from search paths, and the session bus address is
+//! recovered if the browser did not pass it on.
+//!
+//! Spawned programs are also detached: they run in their own session with
+//! standard streams on `/dev/null`, so closing the browser does not take the
+//! file manager with it.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Variables that point programs at a packaged app's private files, or that
+/// belong to qmpo's own start.
+const STRIPPED: &[&str] = &[
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "GTK_PATH",
+    "GTK_EXE_PREFIX",
+    "GTK_DATA_PREFIX",
+    "GTK_IM_MODULE_FILE",
+    "GTK_MODULES",
+    "GIO_MODULE_DIR",
+    "GIO_EXTRA_MODULES",
+    "GIO_LAUNCHED_DESKTOP_FILE",
+    "GIO_LAUNCHED_DESKTOP_FILE_PID",
+    "GDK_PIXBUF_MODULE_FILE",
+    "GDK_PIXBUF_MODULEDIR",
+    "GSETTINGS_SCHEMA_DIR",
+    "GST_PLUGIN_PATH",
+    "GST_PLUGIN_SYSTEM_PATH",
+    "LOCPATH",
+    "PYTHONHOME",
+    "PYTHONPATH",
+    "QT_PLUGIN_PATH",
+    "QT_QPA_PLATFORM_PLUGIN_PATH",
+    "APPDIR",
+    "APPIMAGE",
+    "ARGV0",
+    "OWD",
+    "SNAP",
+    // Activation tokens are used once; launchers get the pending one explicitly
+    "XDG_ACTIVATION_TOKEN",
+    "DESKTOP_STARTUP_ID",
+    // Socket activation of `qmpo daemon` passes its socket to qmpo only
+    "LISTEN_PID",
+    "LISTEN_FDS",
+    "LISTEN_FDNAMES",
+];
+
+/// Prefixes of variables set by Snap and Flatpak for the confined app.
+const STRIPPED_PREFIXES: &[&str] = &["SNAP_", "FLATPAK_", "APPIMAGE_"];
+
+/// Search path variables from which bundle directories are removed.
+const SEARCH_PATHS: &[&str] = &["PATH", "XDG_DATA_DIRS", "XDG_CONFIG_DIRS"];
+
+/// Build a command for `program` with a clean environment.
+///
+/// With `detach`, the program runs in a new session with its standard
+/// streams redirected to `/dev/null`.
+pub fn command(program: &OsStr, args: &[OsString], detach: bool) -> Command {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    let runtime_bus = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join("bus"))
+        .filter(|bus| bus.exists());
+    command.env_clear();
+    command.envs(environment(std::env::vars_os(), runtime_bus.as_deref()));
+
+    // The browser's working directory may be inside its bundle, or gone
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    command.current_dir(
+        home.filter(|home| home.is_dir())
+            .unwrap_or_else(|| PathBuf::from("/")),
+    );
+
+    if detach {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: setsid is async-signal-safe and touches no memory of the parent
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    command
+}
+
+/// The environment for launched programs, given qmpo's own.
+///
+/// `runtime_bus` is the session bus socket in the runtime directory, used when
+/// `DBUS_SESSION_BUS_ADDRESS` is missing.
+fn environment<I>(vars: I, runtime_bus: Option<&Path>) -> Vec<(OsString, OsString)>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let vars: Vec<(OsString, OsString)> = vars.into_iter().collect();
+    let get = |name: &str| {
+        vars.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    // Directories of the package qmpo or the browser runs from
+    let bundles: Vec<PathBuf> = ["SNAP", "APPDIR"]
+        .into_iter()
+        .filter_map(get)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect();
+
+    let mut clean: Vec<(OsString, OsString)> = vars
+        .iter()
+        .filter(|(key, _)| !is_stripped(key))
+        .filter_map(|(key, value)| {
+            if !SEARCH_PATHS.iter().any(|name| key == name) {
+                return Some((key.clone(), value.clone()));
+            }
+            let value = without_bundles(value, &bundles);
+            (!value.is_empty()).then(|| (key.clone(), value))
+        })
+        .collect();
+
+    if get("DBUS_SESSION_BUS_ADDRESS").is_none()
+        && let Some(bus) = runtime_bus
+    {
+        let mut address = OsString::from("unix:path=");
+        address.push(bus);
+        clean.push(("DBUS_SESSION_BUS_ADDRESS".into(), address));
+    }
+    clean
+}
+
+fn is_stripped(key: &OsStr) -> bool {
+    let Some(key) = key.to_str() else {
+        return false;
+    };
+    STRIPPED.contains(&key) || STRIPPED_PREFIXES.iter().any(|p| key.starts_with(p))
+}
+
+/// Remove entries beneath any of `bundles` from a `:`-separated path list.
+fn without_bundles(value: &OsStr, bundles: &[PathBuf]) -> OsString {
+    let kept: Vec<PathBuf> = std::env::split_paths(value)
+        .filter(|entry| !entry.as_os_str().is_empty())
+        .filter(|entry| !bundles.iter().any(|bundle| entry.starts_with(bundle)))
+        .collect();
+    std::env::join_paths(kept).unwrap_or_else(|_| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect()
+    }
+
+    fn get<'a>(env: &'a [(OsString, OsString)], name: &str) -> Option<&'a OsStr> {
+        env.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_os_str())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_snap_environment_is_cleaned() {
+        let env = environment(
+            vars(&[
+                ("HOME", "/home/alice"),
+                ("DISPLAY", ":0"),
+                ("WAYLAND_DISPLAY", "wayland-0"),
+                ("XDG_RUNTIME_DIR", "/run/user/1000"),
+                ("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/user/1000/bus"),
+                ("SNAP", "/snap/firefox/1234"),
+                ("SNAP_NAME", "firefox"),
+                ("GTK_PATH", "/snap/firefox/1234/usr/lib/gtk-3.0"),
+                ("GIO_MODULE_DIR", "/snap/firefox/1234/gio"),
+                ("LD_LIBRARY_PATH", "/snap/firefox/1234/usr/lib"),
+                ("PATH", "/snap/firefox/1234/usr/bin:/usr/bin:/bin"),
+                ("XDG_DATA_DIRS", "/snap/firefox/1234/usr/share"),
+            ]),
+            None,
+        );
+
+        assert_eq!(get(&env, "DISPLAY"), Some(OsStr::new(":0")));
+        assert_eq!(get(&env, "WAYLAND_DISPLAY"), Some(OsStr::new("wayland-0")));
+        assert_eq!(
+            get(&env, "DBUS_SESSION_BUS_ADDRESS"),
+            Some(OsStr::new("unix:path=/run/user/1000/bus"))
+        );
+        for name in [
+            "SNAP",
+            "SNAP_NAME",
+            "GTK_PATH",
+            "GIO_MODULE_DIR",
+            "LD_LIBRARY_PATH",
+        ] {
+            assert_eq!(get(&env, name), None, "{name}");
+        }
+        assert_eq!(get(&env, "PATH"), Some(OsStr::new("/usr/bin:/bin")));
+        // A search path left empty is dropped so programs use their defaults
+        assert_eq!(get(&env, "XDG_DATA_DIRS"), None);
+    }
+
+    #[test]
+    fn test_socket_activation_not_inherited() {
+        let env = environment(
+            vars(&[
+                ("HOME", "/home/alice"),
+                ("LISTEN_PID", "4242"),
+                ("LISTEN_FDS", "1"),
+                ("LISTEN_FDNAMES", "qmpo.socket"),
+            ]),
+            None,
+        );
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            assert_eq!(get(&env, name), None, "{name}");
+        }
+        assert_eq!(get(&env, "HOME"), Some(OsStr::new("/home/alice")));
+    }
+
+    #[test]
+    fn test_bus_address_recovered() {
+        let bus = Path::new("/run/user/1000/bus");
+        let env = environment(vars(&[("HOME", "/home/alice")]), Some(bus));
+        assert_eq!(
+            get(&env, "DBUS_SESSION_BUS_ADDRESS"),
+            Some(OsStr::new("unix:path=/run/user/1000/bus"))
+        );
+
+        let env = environment(
+            vars(&[("DBUS_SESSION_BUS_ADDRESS", "unix:abstract=/tmp/x")]),
+            Some(bus),
+        );
+        assert_eq!(
+            get(&env, "DBUS_SESSION_BUS_ADDRESS"),
+            Some(OsStr::new("unix:abstract=/tmp/x"))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_detached_child_leads_its_session() {
+        // Field 6 of /proc/<pid>/stat is the session ID
+        let is_leader = |detach| {
+            command(
+                OsStr::new("sh"),
+                &[
+                    "-c".into(),
+                    r#"read -r _ _ _ _ _ sid _ < /proc/$$/stat; [ "$sid" = "$$" ]"#.into(),
+                ],
+                detach,
+            )
+            .status()
+            .unwrap()
+            .success()
+        };
+        assert!(is_leader(true));
+        assert!(!is_leader(false));
+    }
+}
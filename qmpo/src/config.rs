@@ -0,0 +1,411 @@
+//! Configuration file support for qmpo.
+//!
+//! Reads `config.toml` from `~/.config/qmpo/` (Linux),
+//! `~/Library/Application Support/qmpo/` (macOS),
+//! or `%APPDATA%\qmpo\config\` (Windows).
+//! A missing file is not an error; every setting has a default.
+//!
+//! Settings that must not be overridden by the user (currently `[signing]`) are
+//! read from the system configuration instead: `/etc/qmpo/config.toml` (Linux),
+//! `/Library/Application Support/qmpo/config.toml` (macOS),
+//! or `%ProgramData%\qmpo\config.toml` (Windows).
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::debounce::DebounceConfig;
+use crate::error::{QmpoError, Result};
+use crate::launcher::LauncherConfig;
+use crate::log::LogConfig;
+use crate::lookup::LookupConfig;
+use crate::mapping::Mapping;
+use crate::placeholder::PlaceholderConfig;
+use crate::privacy::PrivacyConfig;
+use crate::probe::ProbeConfig;
+use crate::sandbox::SandboxConfig;
+use crate::uri::UriConfig;
+
+/// Default TCP port for `qmpo serve`.
+pub const DEFAULT_SERVE_PORT: u16 = 47281;
+
+/// Top-level configuration.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings for the local HTTP bridge (`qmpo serve`).
+    pub serve: ServeConfig,
+
+    /// Logging level, format and rotation.
+    pub log: LogConfig,
+
+    /// Redaction of opened paths in logs and history.
+    pub privacy: PrivacyConfig,
+
+    /// Deadline for filesystem checks on network paths.
+    pub probe: ProbeConfig,
+
+    /// Coalescing of repeated clicks on the same link.
+    pub debounce: DebounceConfig,
+
+    /// How the file manager is started.
+    pub launcher: LauncherConfig,
+
+    /// Hardened mode (Landlock and seccomp on Linux).
+    pub sandbox: SandboxConfig,
+
+    /// Decoding of URI paths.
+    pub uri: UriConfig,
+
+    /// Fallback lookups for names that differ in normalization or case.
+    pub lookup: LookupConfig,
+
+    /// Environment variables links may name as placeholders.
+    pub placeholders: PlaceholderConfig,
+
+    /// Path prefixes rewritten before opening, tried in order.
+    pub mappings: Vec<Mapping>,
+
+    /// Link signing policy, taken from the system configuration only.
+    #[serde(skip)]
+    pub signing: SigningConfig,
+}
+
+/// Settings read from the system configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SystemConfig {
+    signing: SigningConfig,
+}
+
+/// Settings for the local HTTP bridge.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    /// TCP port to listen on. The bridge always binds to `127.0.0.1`.
+    pub port: u16,
+    /// Origins allowed to call the bridge from a browser,
+    /// e.g. `https://intranet.example.com`.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_SERVE_PORT,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+/// Settings for signed links.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningConfig {
+    /// Shared HMAC key used to sign and verify links.
+    pub key: Option<String>,
+    /// Whether unsigned links are accepted.
+    pub mode: SigningMode,
+}
+
+/// Whether unsigned links are accepted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SigningMode {
+    /// Unsigned links are accepted; signed links must still verify.
+    #[default]
+    Optional,
+    /// Only links with a valid, unexpired signature are accepted.
+    SignedOnly,
+}
+
+impl Config {
+    /// Load the user and system configuration, falling back to defaults
+    /// for files that do not exist.
+    pub fn load() -> Result<Self> {
+        let mut config = match config_path() {
+            Some(path) => Self::load_from(&path)?,
+            None => Self::default(),
+        };
+        let system: SystemConfig = read_toml(&system_config_path())?.unwrap_or_default();
+        config.signing = system.signing;
+        Ok(config)
+    }
+
+    /// Load user configuration from a specific file.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Ok(read_toml(path)?.unwrap_or_default())
+    }
+}
+
+/// Read and deserialize a TOML file, returning `None` if it does not exist.
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    toml::from_str(&text)
+        .map(Some)
+        .map_err(|e| QmpoError::Config(format!("{}: {e}", path.display())))
+}
+
+/// Get the user configuration file path.
+pub fn config_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "qmpo").map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+/// Get the per-user directory for locks and sockets, creating it if needed.
+///
+/// This is `$XDG_RUNTIME_DIR/qmpo` where available, otherwise the cache directory.
+pub fn runtime_dir() -> std::io::Result<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "qmpo").ok_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, "could not determine runtime directory")
+    })?;
+    let dir = dirs.runtime_dir().unwrap_or(dirs.cache_dir()).to_path_buf();
+
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+/// Get the system configuration file path.
+#[cfg(target_os = "windows")]
+pub fn system_config_path() -> PathBuf {
+    let program_data = std::env::var_os("ProgramData").unwrap_or_else(|| "C:\\ProgramData".into());
+    PathBuf::from(program_data).join("qmpo").join("config.toml")
+}
+
+/// Get the system configuration file path.
+#[cfg(target_os = "macos")]
+pub fn system_config_path() -> PathBuf {
+    PathBuf::from("/Library/Application Support/qmpo/config.toml")
+}
+
+/// Get the system configuration file path.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub fn system_config_path() -> PathBuf {
+    PathBuf::from("/etc/qmpo/config.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Config {
+        fn parse(text: &str) -> std::result::Result<Self, toml::de::Error> {
+            toml::from_str(text)
+        }
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.serve.port, DEFAULT_SERVE_PORT);
+        assert!(config.serve.allowed_origins.is_empty());
+    }
+
+    #[test]
+    fn test_serve_section() {
+        let config = Config::parse(
+            r#"
+            [serve]
+            port = 8080
+            allowed_origins = ["https://intranet.example.com"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.serve.port, 8080);
+        assert_eq!(
+            config.serve.allowed_origins,
+            vec!["https://intranet.example.com".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_log_section() {
+        let config = Config::parse(
+            r#"
+            [log]
+            level = "debug"
+            backend = "journald"
+            format = "json"
+            keep = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log.level, crate::log::Level::Debug);
+        assert_eq!(config.log.backend, crate::log::Backend::Journald);
+        assert_eq!(config.log.format, crate::log::Format::Json);
+        assert_eq!(config.log.keep, 5);
+        assert_eq!(config.log.max_size, LogConfig::default().max_size);
+    }
+
+    #[test]
+    fn test_privacy_section() {
+        let config = Config::parse(
+            r#"
+            [privacy]
+            redact = "truncate"
+            components = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.privacy.redact, crate::privacy::Redaction::Truncate);
+        assert_eq!(config.privacy.components, 3);
+        assert!(Config::parse("[privacy]\nredact = \"blur\"").is_err());
+    }
+
+    #[test]
+    fn test_probe_section() {
+        let config = Config::parse(
+            r#"
+            [probe]
+            timeout_ms = 500
+            on_timeout = "passthrough"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.probe.timeout_ms, 500);
+        assert_eq!(
+            config.probe.on_timeout,
+            crate::probe::OnTimeout::Passthrough
+        );
+        assert_eq!(Config::default().probe.timeout_ms, 3000);
+        assert!(Config::parse("[probe]\non_timeout = \"retry\"").is_err());
+    }
+
+    #[test]
+    fn test_debounce_section() {
+        let config = Config::parse("[debounce]\nwindow_ms = 0").unwrap();
+        assert_eq!(config.debounce.window_ms, 0);
+        assert_eq!(Config::default().debounce.window_ms, 2000);
+    }
+
+    #[test]
+    fn test_sandbox_and_launcher_sections() {
+        let config = Config::parse("[sandbox]\nenabled = true").unwrap();
+        assert!(config.sandbox.enabled);
+        assert_eq!(
+            crate::launcher::backend(&config),
+            crate::launcher::Backend::Dbus
+        );
+
+        let config = Config::parse("[launcher]\nbackend = \"dbus\"").unwrap();
+        assert_eq!(config.launcher.backend, crate::launcher::Backend::Dbus);
+        let config = Config::parse("[launcher]\nbackend = \"explorer\"").unwrap();
+        assert_eq!(config.launcher.backend, crate::launcher::Backend::Explorer);
+        assert_eq!(
+            crate::launcher::backend(&Config::default()),
+            crate::launcher::Backend::Auto
+        );
+
+        // The portal spawns nothing, so hardened mode keeps it
+        let config =
+            Config::parse("[sandbox]\nenabled = true\n[launcher]\nbackend = \"portal\"").unwrap();
+        assert_eq!(
+            crate::launcher::backend(&config),
+            crate::launcher::Backend::Portal
+        );
+    }
+
+    #[test]
+    fn test_uri_section() {
+        let config = Config::parse("[uri]\nfallback_encodings = [\"cp932\", \"latin1\"]").unwrap();
+        let names: Vec<&str> = config
+            .uri
+            .fallback_encodings
+            .iter()
+            .map(|encoding| encoding.name())
+            .collect();
+        assert_eq!(names, ["Shift_JIS", "windows-1252"]);
+        assert!(Config::default().uri.fallback_encodings.is_empty());
+        assert!(!Config::default().uri.local_hostname);
+        assert!(
+            Config::parse("[uri]\nlocal_hostname = true")
+                .unwrap()
+                .uri
+                .local_hostname
+        );
+
+        let err = Config::parse("[uri]\nfallback_encodings = [\"utf-16\"]").unwrap_err();
+        assert!(err.to_string().contains("unsupported encoding: utf-16"));
+    }
+
+    #[test]
+    fn test_lookup_section() {
+        let config = Config::parse("[lookup]\nunicode = true").unwrap();
+        assert!(config.lookup.unicode);
+        assert!(!config.lookup.ignore_case);
+        assert!(config.lookup.is_enabled());
+        assert!(!Config::default().lookup.is_enabled());
+    }
+
+    #[test]
+    fn test_placeholders_section() {
+        let config = Config::parse("[placeholders]\nenv = [\"PROJECTS\"]").unwrap();
+        assert_eq!(config.placeholders.env, ["PROJECTS"]);
+        assert!(Config::default().placeholders.env.is_empty());
+        assert!(Config::parse("[placeholders]\nhome = true").is_err());
+    }
+
+    #[test]
+    fn test_mappings() {
+        let config = Config::parse(
+            r#"
+            [[mappings]]
+            from = '\\fileserver\share'
+            to = "/mnt/share"
+
+            [[mappings]]
+            from = 'S:\'
+            to = "/mnt/s"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mappings.len(), 2);
+        assert_eq!(config.mappings[0].from, r"\\fileserver\share");
+        assert_eq!(config.mappings[1].to, "/mnt/s");
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        assert!(Config::parse("[serve]\nprot = 8080\n").is_err());
+    }
+
+    #[test]
+    fn test_signing_not_accepted_in_user_config() {
+        assert!(Config::parse("[signing]\nmode = \"optional\"\n").is_err());
+    }
+
+    #[test]
+    fn test_system_signing_section() {
+        let system: SystemConfig = toml::from_str(
+            r#"
+            [signing]
+            key = "0123456789abcdef"
+            mode = "signed-only"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(system.signing.key.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(system.signing.mode, SigningMode::SignedOnly);
+    }
+
+    #[test]
+    fn test_missing_file_uses_defaults() {
+        let path = std::env::temp_dir().join("qmpo_test_missing_config.toml");
+        let _ = fs::remove_file(&path);
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.serve.port, DEFAULT_SERVE_PORT);
+    }
+}
@@ -0,0 +1,345 @@
+//! Resident daemon that opens URIs on behalf of short-lived `qmpo` processes.
+//!
+//! `qmpo daemon` listens on `daemon.sock` in the runtime directory, or on the
+//! socket passed by systemd socket activation (`LISTEN_FDS`). `qmpo <uri>`
+//! sends its URIs there when the socket accepts a connection, before loading
+//! any configuration, and opens them itself otherwise. The daemon coalesces
+//! repeated clicks like a standalone `qmpo` does.
+//!
+//! Each connection carries one request and one reply, each a JSON line:
+//!
+//! - request: `{"uris":["directory:///srv/share"]}`
+//! - reply: `{"results":[{"ok":true}]}`, with `error`, `kind` and `code` for failures
+//!
+//! The configuration is reloaded when either config file changes. The socket
+//! is only reachable by the user: the runtime directory is private and the
+//! socket itself is created with mode 0600.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Config};
+use crate::error::{QmpoError, Result};
+use crate::{activation, log, privacy};
+
+/// File name of the socket in the runtime directory.
+const SOCKET_NAME: &str = "daemon.sock";
+/// First file descriptor passed by socket activation.
+const SD_LISTEN_FDS_START: i32 = 3;
+/// Maximum size of a request line.
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+/// How long the daemon waits for a client to send its request.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client waits for the URIs to be opened.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Callback that opens URIs with the current configuration.
+type OpenFn<'a> = dyn Fn(&Config, &[String]) -> Vec<Result<()>> + 'a;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    uris: Vec<String>,
+    /// The client's window activation token, used for the launch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    activation_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Reply {
+    results: Vec<Outcome>,
+}
+
+/// The result of opening one URI.
+#[derive(Debug, Serialize, Deserialize)]
+struct Outcome {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<i32>,
+}
+
+impl From<&Result<()>> for Outcome {
+    fn from(result: &Result<()>) -> Self {
+        match result {
+            Ok(()) => Self {
+                ok: true,
+                error: None,
+                kind: None,
+                code: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                error: Some(e.to_string()),
+                kind: Some(e.kind().to_string()),
+                code: Some(e.exit_code()),
+            },
+        }
+    }
+}
+
+impl From<Outcome> for Result<()> {
+    fn from(outcome: Outcome) -> Self {
+        if outcome.ok {
+            return Ok(());
+        }
+        Err(QmpoError::forwarded(
+            outcome.error.unwrap_or_default(),
+            outcome.kind.as_deref().unwrap_or_default(),
+            outcome.code.unwrap_or(1),
+        ))
+    }
+}
+
+fn socket_path() -> io::Result<PathBuf> {
+    Ok(config::runtime_dir()?.join(SOCKET_NAME))
+}
+
+/// Hand `uris` to a running daemon.
+///
+/// Returns `None` if no daemon accepts the connection, in which case the caller
+/// opens the URIs itself. Once connected, a lost reply is reported as a launch
+/// failure rather than retried, since the daemon may already have opened them.
+pub fn forward(uris: &[String]) -> Option<Vec<Result<()>>> {
+    let stream = UnixStream::connect(socket_path().ok()?).ok()?;
+    Some(exchange(&stream, uris).unwrap_or_else(|e| {
+        uris.iter()
+            .map(|_| {
+                Err(QmpoError::LaunchFailed(format!(
+                    "no reply from qmpo daemon: {e}"
+                )))
+            })
+            .collect()
+    }))
+}
+
+fn exchange(stream: &UnixStream, uris: &[String]) -> io::Result<Vec<Result<()>>> {
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut request = serde_json::to_string(&Request {
+        uris: uris.to_vec(),
+        activation_token: activation::take(),
+    })?;
+    request.push('\n');
+    let mut writer = stream;
+    writer.write_all(request.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let reply: Reply = serde_json::from_str(&line)?;
+    if reply.results.len() != uris.len() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "reply does not match request",
+        ));
+    }
+    Ok(reply.results.into_iter().map(Result::from).collect())
+}
+
+/// Run the daemon until the process is terminated.
+pub fn run(config: Config, open: &OpenFn<'_>) -> Result<()> {
+    let listener = listener()?;
+    let mut reloader = Reloader::new(config);
+    log::info("Daemon listening");
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve_connection(&stream, reloader.current(), open));
+        if let Err(e) = result {
+            log::error(&format!("Daemon connection failed: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// The socket passed by systemd, or a newly bound one in the runtime directory.
+fn listener() -> io::Result<UnixListener> {
+    if let Some(listener) = activated_listener()? {
+        return Ok(listener);
+    }
+
+    let path = socket_path()?;
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("a daemon is already listening on {}", path.display()),
+        ));
+    }
+    // A socket left behind by a daemon that did not exit cleanly
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn activated_listener() -> io::Result<Option<UnixListener>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    if listen_fds(pid.as_deref(), fds.as_deref(), std::process::id()) == 0 {
+        return Ok(None);
+    }
+
+    use std::os::fd::FromRawFd;
+    // SAFETY: LISTEN_PID names this process, so systemd passed a listening
+    // socket at SD_LISTEN_FDS_START that nothing else owns.
+    let inherited = unsafe { UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    // The inherited descriptor lacks close-on-exec; a duplicate has it, so the
+    // socket does not leak into launched file managers
+    let listener = inherited.try_clone()?;
+    drop(inherited);
+    Ok(Some(listener))
+}
+
+/// Number of sockets passed by socket activation, or 0 if they are not for `own_pid`.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> u32 {
+    match (
+        pid.and_then(|p| p.parse::<u32>().ok()),
+        fds.and_then(|n| n.parse().ok()),
+    ) {
+        (Some(pid), Some(fds)) if pid == own_pid => fds,
+        _ => 0,
+    }
+}
+
+fn serve_connection(stream: &UnixStream, config: &Config, open: &OpenFn<'_>) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_SIZE)).read_line(&mut line)?;
+    // Another `qmpo daemon` checking whether the socket is in use
+    if line.is_empty() {
+        return Ok(());
+    }
+    let request: Request = serde_json::from_str(&line)?;
+
+    activation::offer(request.activation_token);
+    let results = open(config, &request.uris);
+    let mut reply = serde_json::to_string(&Reply {
+        results: results.iter().map(Outcome::from).collect(),
+    })?;
+    reply.push('\n');
+    let mut writer = stream;
+    writer.write_all(reply.as_bytes())
+}
+
+/// Keeps the configuration in sync with the config files.
+struct Reloader {
+    config: Config,
+    stamps: Vec<Option<SystemTime>>,
+}
+
+impl Reloader {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            stamps: stamps(),
+        }
+    }
+
+    /// The configuration, reloaded first if a config file changed.
+    ///
+    /// An invalid file is logged and the previous configuration kept.
+    fn current(&mut self) -> &Config {
+        let stamps = stamps();
+        if stamps != self.stamps {
+            self.stamps = stamps;
+            match Config::load() {
+                Ok(config) => {
+                    log::init(&config.log);
+                    if let Err(e) = privacy::init(&config.privacy) {
+                        log::error(&format!("Keeping previous privacy settings: {e}"));
+                    }
+                    self.config = config;
+                    log::info("Configuration reloaded");
+                }
+                Err(e) => log::error(&format!("Keeping previous configuration: {e}")),
+            }
+        }
+        &self.config
+    }
+}
+
+/// Modification times of the user and system config files.
+fn stamps() -> Vec<Option<SystemTime>> {
+    config::config_path()
+        .into_iter()
+        .chain([config::system_config_path()])
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+    }
+
+    #[test]
+    fn test_outcome_round_trip() {
+        let result: Result<()> = Err(QmpoError::NotFound(PathBuf::from("/missing")));
+        let json = serde_json::to_string(&Outcome::from(&result)).unwrap();
+        let back: Result<()> = serde_json::from_str::<Outcome>(&json).unwrap().into();
+        let back = back.unwrap_err();
+        assert_eq!(back.to_string(), "path does not exist: /missing");
+        assert_eq!(back.kind(), "not_found");
+        assert_eq!(back.exit_code(), 3);
+
+        let json = serde_json::to_string(&Outcome::from(&Ok(()))).unwrap();
+        assert_eq!(json, r#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn test_forwarded_request() {
+        let dir = std::env::temp_dir().join(format!("qmpo_test_daemon_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SOCKET_NAME);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(&stream, &Config::default(), &|_, uris| {
+                uris.iter()
+                    .map(|uri| match uri.as_str() {
+                        "directory:///ok" => Ok(()),
+                        _ => Err(QmpoError::LaunchFailed("no launcher succeeded".into())),
+                    })
+                    .collect()
+            })
+            .unwrap();
+        });
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let uris = [
+            "directory:///ok".to_string(),
+            "directory:///bad".to_string(),
+        ];
+        let results = exchange(&stream, &uris).unwrap();
+        server.join().unwrap();
+
+        assert!(results[0].is_ok());
+        let err = results[1].as_ref().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to launch file manager: no launcher succeeded"
+        );
+        assert_eq!(err.exit_code(), 6);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,167 @@
+//! Coalescing of repeated clicks on the same link.
+//!
+//! A double click, or the browser extension re-firing a link, often starts two
+//! `qmpo` processes for one URI. Before launching, each process records the
+//! URI in `launches` in the runtime directory while holding `launches.lock`.
+//! A URI already recorded within `[debounce] window_ms` is not opened again.
+//! Only a hash of each URI is stored.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config;
+
+/// Exit code of a process whose URIs were all opened by another process just before.
+pub const EXIT_COALESCED: i32 = 10;
+
+/// Settings for the `[debounce]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebounceConfig {
+    /// Milliseconds during which the same URI is opened only once (0 disables).
+    pub window_ms: u64,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self { window_ms: 2000 }
+    }
+}
+
+/// Record that `uri` is about to be opened.
+///
+/// Returns `false` if the same URI was recorded within `window`, in which case
+/// it should not be opened again.
+pub fn claim(uri: &str, window: Duration) -> io::Result<bool> {
+    if window.is_zero() {
+        return Ok(true);
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    update(|recent| record(recent, &key(uri), now, window.as_millis()))
+}
+
+/// Forget a claim on `uri` whose open failed, so that retrying it right away
+/// opens it rather than being coalesced.
+pub fn release(uri: &str, window: Duration) -> io::Result<()> {
+    if window.is_zero() {
+        return Ok(());
+    }
+    update(|recent| ((), forget(recent, &key(uri))))
+}
+
+/// Rewrite `launches` with `f` while holding `launches.lock`.
+fn update<T>(f: impl FnOnce(&str) -> (T, String)) -> io::Result<T> {
+    let dir = config::runtime_dir()?;
+    let lock = File::create(dir.join("launches.lock"))?;
+    lock.lock()?;
+
+    let path = dir.join("launches");
+    let recent = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let (result, recent) = f(&recent);
+    fs::write(&path, recent)?;
+    // The lock is released when `lock` is dropped
+    Ok(result)
+}
+
+/// Add `key` to the `<millis> <key>` lines of `recent`, dropping expired lines.
+///
+/// Returns whether `key` was absent, and the new contents.
+fn record(recent: &str, key: &str, now: u128, window: u128) -> (bool, String) {
+    let mut fresh = true;
+    let mut kept = String::new();
+    for line in recent.lines() {
+        let Some((ts, seen)) = line.split_once(' ') else {
+            continue;
+        };
+        let Ok(ts) = ts.parse::<u128>() else {
+            continue;
+        };
+        if now.saturating_sub(ts) >= window {
+            continue;
+        }
+        if seen == key {
+            fresh = false;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if fresh {
+        kept.push_str(&format!("{now} {key}\n"));
+    }
+    (fresh, kept)
+}
+
+/// Remove the lines of `key` from `recent`.
+fn forget(recent: &str, key: &str) -> String {
+    recent
+        .lines()
+        .filter(|line| line.split_once(' ').is_none_or(|(_, seen)| seen != key))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+fn key(uri: &str) -> String {
+    Sha256::digest(uri.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_coalesces_within_window() {
+        let (fresh, recent) = record("", "a", 1000, 2000);
+        assert!(fresh);
+        assert_eq!(recent, "1000 a\n");
+
+        let (fresh, recent) = record(&recent, "a", 2500, 2000);
+        assert!(!fresh);
+        // The first launch keeps its timestamp, so a steady stream of clicks
+        // still opens once per window
+        assert_eq!(recent, "1000 a\n");
+
+        let (fresh, recent) = record(&recent, "b", 2500, 2000);
+        assert!(fresh);
+        assert_eq!(recent, "1000 a\n2500 b\n");
+    }
+
+    #[test]
+    fn test_record_drops_expired_and_garbage() {
+        let (fresh, recent) = record("1000 a\nbogus\nx a\n", "a", 3000, 2000);
+        assert!(fresh);
+        assert_eq!(recent, "3000 a\n");
+    }
+
+    #[test]
+    fn test_retry_after_failure() {
+        let (_, recent) = record("", "a", 1000, 2000);
+        let (_, recent) = record(&recent, "b", 1200, 2000);
+        // Opening `a` failed; a retry within the window opens it again
+        let recent = forget(&recent, "a");
+        assert_eq!(recent, "1200 b\n");
+        let (fresh, recent) = record(&recent, "a", 1500, 2000);
+        assert!(fresh);
+        assert_eq!(recent, "1200 b\n1500 a\n");
+    }
+
+    #[test]
+    fn test_key_hides_uri() {
+        let key = key("directory:///srv/hr");
+        assert_eq!(key.len(), 64);
+        assert!(!key.contains("srv"));
+    }
+}
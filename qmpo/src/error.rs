@@ -1,5 +1,8 @@
 //! Error types for qmpo-core.
 
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
 use thiserror::Error;
 
 /// Errors that can occur during URI parsing and path conversion.
@@ -37,10 +40,195 @@ pub enum QmpoError {
     #[error("UTF-8 decode error: {0}")]
     Utf8Error(#[from] std::string::FromUtf8Error),
 
+    /// The target path does not exist.
+    #[error("path does not exist: {}", .0.display())]
+    NotFound(PathBuf),
+
+    /// The target path exists but may not be accessed.
+    #[error("permission denied: {}", .0.display())]
+    PermissionDenied(PathBuf),
+
+    /// The link was refused by policy (e.g. an unsigned, invalid or expired signature).
+    #[error("refused by policy: {0}")]
+    PolicyDenied(String),
+
+    /// The file manager could not be started.
+    #[error("failed to launch file manager: {0}")]
+    LaunchFailed(String),
+
+    /// The path cannot be mapped to a location on this machine
+    /// (e.g. a Windows drive or UNC path on Linux).
+    #[error("no mapping for path: {}", .0.display())]
+    MappingFailed(PathBuf),
+
+    /// A filesystem operation timed out (e.g. an unreachable network share).
+    #[error("timed out: {0}")]
+    Timeout(String),
+
+    /// The configuration file is invalid.
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
     /// An I/O error occurred.
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+
+    /// Another `qmpo` process opened the same URI just before.
+    #[error("already opened by another qmpo process")]
+    Coalesced,
+
+    /// An error reported by `qmpo daemon` for a URI forwarded to it.
+    #[error("{message}")]
+    Forwarded {
+        message: String,
+        kind: &'static str,
+        code: i32,
+    },
+}
+
+/// Every value returned by [`QmpoError::kind`].
+const KINDS: [&str; 10] = [
+    "invalid_uri",
+    "not_found",
+    "permission_denied",
+    "policy_denied",
+    "launch_failed",
+    "mapping_failed",
+    "timeout",
+    "config",
+    "io",
+    "coalesced",
+];
+
+impl QmpoError {
+    /// Rebuild an error from its message, kind and exit code.
+    pub fn forwarded(message: String, kind: &str, code: i32) -> Self {
+        let kind = KINDS.into_iter().find(|k| *k == kind).unwrap_or("other");
+        Self::Forwarded {
+            message,
+            kind,
+            code,
+        }
+    }
+
+    /// Classify an I/O error raised while accessing `path`.
+    pub fn from_io(err: std::io::Error, path: &Path) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound(path.to_path_buf()),
+            ErrorKind::PermissionDenied => Self::PermissionDenied(path.to_path_buf()),
+            ErrorKind::TimedOut => Self::Timeout(format!("{}: {err}", path.display())),
+            _ => Self::Io(err),
+        }
+    }
+
+    /// Process exit code for this error. See the "Exit Codes" section of the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 1,
+            Self::InvalidScheme(_)
+            | Self::InvalidUri(_)
+            | Self::EmptyPath
+            | Self::UrlParse(_)
+            | Self::Utf8Error(_) => 2,
+            Self::NotFound(_) => 3,
+            Self::PermissionDenied(_) => 4,
+            Self::PolicyDenied(_) => 5,
+            Self::LaunchFailed(_) => 6,
+            Self::MappingFailed(_) => 7,
+            Self::Timeout(_) => 8,
+            Self::Config(_) => 9,
+            // debounce::EXIT_COALESCED
+            Self::Coalesced => 10,
+            Self::Forwarded { code, .. } => *code,
+        }
+    }
+
+    /// Stable, machine-readable name of the error kind (used by `--json`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidScheme(_)
+            | Self::InvalidUri(_)
+            | Self::EmptyPath
+            | Self::UrlParse(_)
+            | Self::Utf8Error(_) => "invalid_uri",
+            Self::NotFound(_) => "not_found",
+            Self::PermissionDenied(_) => "permission_denied",
+            Self::PolicyDenied(_) => "policy_denied",
+            Self::LaunchFailed(_) => "launch_failed",
+            Self::MappingFailed(_) => "mapping_failed",
+            Self::Timeout(_) => "timeout",
+            Self::Config(_) => "config",
+            Self::Io(_) => "io",
+            Self::Coalesced => "coalesced",
+            Self::Forwarded { kind, .. } => kind,
+        }
+    }
+
+    /// Whether another process opened the URI instead, here or in `qmpo daemon`.
+    pub fn is_coalesced(&self) -> bool {
+        self.kind() == "coalesced"
+    }
 }
 
 /// A specialized [`Result`](std::result::Result) type for qmpo operations.
 pub type Result<T> = std::result::Result<T, QmpoError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_io() {
+        let path = Path::new("/srv/share");
+        let err = |kind| std::io::Error::from(kind);
+        assert!(matches!(
+            QmpoError::from_io(err(ErrorKind::NotFound), path),
+            QmpoError::NotFound(p) if p == path
+        ));
+        assert!(matches!(
+            QmpoError::from_io(err(ErrorKind::PermissionDenied), path),
+            QmpoError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            QmpoError::from_io(err(ErrorKind::TimedOut), path),
+            QmpoError::Timeout(_)
+        ));
+        assert!(matches!(
+            QmpoError::from_io(err(ErrorKind::Other), path),
+            QmpoError::Io(_)
+        ));
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            QmpoError::Io(std::io::Error::other("x")),
+            QmpoError::EmptyPath,
+            QmpoError::NotFound(PathBuf::new()),
+            QmpoError::PermissionDenied(PathBuf::new()),
+            QmpoError::PolicyDenied(String::new()),
+            QmpoError::LaunchFailed(String::new()),
+            QmpoError::MappingFailed(PathBuf::new()),
+            QmpoError::Timeout(String::new()),
+            QmpoError::Config(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(QmpoError::exit_code).collect();
+        codes.dedup();
+        assert_eq!(codes, (1..=9).collect::<Vec<_>>());
+
+        let kinds: Vec<&str> = errors[1..].iter().map(QmpoError::kind).collect();
+        assert_eq!(kinds[..8], KINDS[..8]);
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let err = QmpoError::forwarded("path does not exist: /x".to_string(), "not_found", 3);
+        assert_eq!(err.to_string(), "path does not exist: /x");
+        assert_eq!(err.kind(), "not_found");
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(
+            QmpoError::forwarded(String::new(), "bogus", 1).kind(),
+            "other"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Structured history of opened URIs.
+//!
+//! Each invocation appends one JSON line to `history.jsonl` next to the log file.
+//! When the file grows past [`MAX_HISTORY_SIZE`], the oldest half is dropped
+//! instead of the whole file. Appends, trimming and purging hold a lock on
+//! `history.jsonl.lock`, so a rewrite never loses a concurrent append.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::privacy::{self, Redaction};
+use crate::time;
+
+const MAX_HISTORY_SIZE: u64 = 4 * 1024 * 1024; // 4MB
+
+/// What the file manager was asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Open a directory.
+    Open,
+    /// Open the parent directory with a file selected.
+    Reveal,
+}
+
+impl Action {
+    /// The action taken for a resolved path.
+    pub fn for_file(is_file: bool) -> Self {
+        if is_file { Self::Reveal } else { Self::Open }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Reveal => "reveal",
+        }
+    }
+}
+
+/// Whether the invocation succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// The file manager was launched.
+    Ok,
+    /// The invocation failed before or while launching.
+    Failed,
+}
+
+/// A single history record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch.
+    pub ts: u64,
+    /// The URI as received.
+    pub uri: String,
+    /// The resolved (canonical) path, if resolution got that far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The action taken, if the launcher was reached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<Action>,
+    pub result: Outcome,
+    /// The error message for failed invocations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Where the request came from (e.g. the HTTP `Origin`), when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// The redaction that changed `uri` when it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redacted: Option<Redaction>,
+}
+
+impl Entry {
+    /// Start a record for a URI received now.
+    pub fn new(uri: &str, origin: Option<&str>) -> Self {
+        Self {
+            ts: time::now(),
+            uri: uri.to_string(),
+            path: None,
+            action: None,
+            result: Outcome::Failed,
+            error: None,
+            origin: origin.map(str::to_string),
+            redacted: None,
+        }
+    }
+
+    /// The URI to open for `qmpo history reopen`, unless redaction changed it.
+    pub fn reopen_uri(&self) -> Option<&str> {
+        self.redacted.is_none().then_some(self.uri.as_str())
+    }
+
+    /// Record the result of the invocation.
+    pub fn finish<E: std::fmt::Display>(&mut self, result: &Result<(), E>) {
+        match result {
+            Ok(()) => self.result = Outcome::Ok,
+            Err(e) => {
+                self.result = Outcome::Failed;
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Filters for [`query`].
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Only entries at or after this time (seconds since the Unix epoch).
+    pub since: Option<u64>,
+    /// Only failed entries.
+    pub failed: bool,
+}
+
+/// Get the history file path.
+fn history_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "qmpo").map(|dirs| dirs.data_dir().join("history.jsonl"))
+}
+
+/// Append an entry. Silently fails if the history is not writable.
+pub fn append(entry: &Entry) {
+    if let Some(path) = history_path() {
+        let _ = append_to(&path, entry);
+    }
+}
+
+fn append_to(path: &Path, entry: &Entry) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let _lock = lock(path)?;
+    if fs::metadata(path).is_ok_and(|m| m.len() > MAX_HISTORY_SIZE) {
+        trim(path)?;
+    }
+
+    let uri = privacy::scrub(&entry.uri);
+    let entry = Entry {
+        redacted: (uri != entry.uri).then(privacy::mode),
+        uri,
+        path: entry.path.as_deref().map(privacy::scrub),
+        error: entry.error.as_deref().map(privacy::scrub),
+        ..entry.clone()
+    };
+    let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+    // The lock is released when `_lock` is dropped
+}
+
+/// Lock the history against concurrent rewrites until the result is dropped.
+fn lock(path: &Path) -> io::Result<File> {
+    let lock = File::create(path.with_extension("jsonl.lock"))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+/// Drop the oldest half of the history.
+fn trim(path: &Path) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    let keep = &lines[lines.len() / 2..];
+
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(&tmp, keep.join("\n") + "\n")?;
+    fs::rename(&tmp, path)
+}
+
+/// Read all entries, oldest first. Unparseable lines are skipped.
+pub fn load() -> io::Result<Vec<Entry>> {
+    match history_path() {
+        Some(path) => load_from(&path),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn load_from(path: &Path) -> io::Result<Vec<Entry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Remove entries recorded before `before` (seconds since the Unix epoch).
+/// Returns the number of entries removed.
+pub fn purge(before: u64) -> io::Result<usize> {
+    match history_path() {
+        Some(path) => purge_file(&path, before),
+        None => Ok(0),
+    }
+}
+
+fn purge_file(path: &Path, before: u64) -> io::Result<usize> {
+    if !path.parent().is_some_and(Path::exists) {
+        return Ok(0);
+    }
+
+    let _lock = lock(path)?;
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let (old, kept): (Vec<&str>, Vec<&str>) = text
+        .lines()
+        .partition(|line| serde_json::from_str::<Entry>(line).is_ok_and(|entry| entry.ts < before));
+    if old.is_empty() {
+        return Ok(0);
+    }
+
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(
+        &tmp,
+        kept.iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>(),
+    )?;
+    fs::rename(&tmp, path)?;
+    Ok(old.len())
+}
+
+/// Select entries matching `filter`, newest first, paired with their number.
+///
+/// Entry numbers count from the newest entry (1) and do not depend on the
+/// filter, so `qmpo history reopen N` refers to the same entry either way.
+pub fn query<'a>(entries: &'a [Entry], filter: &Filter) -> Vec<(usize, &'a Entry)> {
+    entries
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, entry)| (i + 1, entry))
+        .filter(|(_, entry)| filter.since.is_none_or(|since| entry.ts >= since))
+        .filter(|(_, entry)| !filter.failed || entry.result == Outcome::Failed)
+        .collect()
+}
+
+/// Look up an entry by its number (1 = newest).
+pub fn nth(entries: &[Entry], n: usize) -> Option<&Entry> {
+    n.checked_sub(1)
+        .and_then(|i| entries.len().checked_sub(i + 1))
+        .map(|i| &entries[i])
+}
+
+/// Format an entry as a single line for `qmpo history`, in UTC if `utc` is set.
+pub fn format_entry(n: usize, entry: &Entry, utc: bool) -> String {
+    let result = match entry.result {
+        Outcome::Ok => "ok",
+        Outcome::Failed => "failed",
+    };
+    let action = entry.action.map_or("-", Action::as_str);
+    let target = entry.path.as_deref().unwrap_or(&entry.uri);
+
+    let mut line = format!(
+        "{n:>4}  {}  {result:<6}  {action:<6}  {target}",
+        time::format_timestamp(entry.ts, utc)
+    );
+    if let Some(origin) = &entry.origin {
+        line.push_str(&format!("  [{origin}]"));
+    }
+    if let Some(error) = &entry.error {
+        line.push_str(&format!("  ({error})"));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: u64, uri: &str, result: Outcome) -> Entry {
+        Entry {
+            ts,
+            uri: uri.to_string(),
+            path: None,
+            action: None,
+            result,
+            error: None,
+            origin: None,
+            redacted: None,
+        }
+    }
+
+    fn sample() -> Vec<Entry> {
+        vec![
+            entry(100, "directory:///a", Outcome::Ok),
+            entry(200, "directory:///b", Outcome::Failed),
+            entry(300, "directory:///c", Outcome::Ok),
+        ]
+    }
+
+    #[test]
+    fn test_query_numbers_newest_first() {
+        let entries = sample();
+        let numbered: Vec<_> = query(&entries, &Filter::default())
+            .into_iter()
+            .map(|(n, entry)| (n, entry.uri.as_str()))
+            .collect();
+        assert_eq!(
+            numbered,
+            vec![
+                (1, "directory:///c"),
+                (2, "directory:///b"),
+                (3, "directory:///a")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_filters_keep_numbers() {
+        let entries = sample();
+        let failed = query(
+            &entries,
+            &Filter {
+                since: None,
+                failed: true,
+            },
+        );
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, 2);
+
+        let recent = query(
+            &entries,
+            &Filter {
+                since: Some(200),
+                failed: false,
+            },
+        );
+        assert_eq!(recent.len(), 2);
+    }
+
+    #[test]
+    fn test_nth() {
+        let entries = sample();
+        assert_eq!(nth(&entries, 1).unwrap().uri, "directory:///c");
+        assert_eq!(nth(&entries, 3).unwrap().uri, "directory:///a");
+        assert!(nth(&entries, 0).is_none());
+        assert!(nth(&entries, 4).is_none());
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut entry = Entry::new("directory:///a", Some("https://intranet.example.com"));
+        entry.finish(&Err::<(), _>("Path does not exist: /a"));
+        assert_eq!(entry.result, Outcome::Failed);
+        assert_eq!(entry.error.as_deref(), Some("Path does not exist: /a"));
+
+        entry.finish::<String>(&Ok(()));
+        assert_eq!(entry.result, Outcome::Ok);
+    }
+
+    #[test]
+    fn test_serialized_form() {
+        let mut entry = entry(100, "directory:///a", Outcome::Ok);
+        entry.path = Some("/a".to_string());
+        entry.action = Some(Action::Open);
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"ts":100,"uri":"directory:///a","path":"/a","action":"open","result":"ok"}"#
+        );
+    }
+
+    #[test]
+    fn test_redacted_entry_not_reopened() {
+        let mut entry = entry(100, "directory:///srv/share/…", Outcome::Ok);
+        assert_eq!(entry.reopen_uri(), Some("directory:///srv/share/…"));
+
+        entry.redacted = Some(Redaction::Truncate);
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.ends_with(r#""redacted":"truncate"}"#), "{line}");
+        let entry: Entry = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry.reopen_uri(), None);
+    }
+
+    #[test]
+    fn test_append_load_and_trim() {
+        let dir = std::env::temp_dir().join("qmpo_test_history");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("history.jsonl");
+
+        for entry in sample() {
+            append_to(&path, &entry).unwrap();
+        }
+        assert_eq!(load_from(&path).unwrap(), sample());
+
+        trim(&path).unwrap();
+        let remaining = load_from(&path).unwrap();
+        assert_eq!(remaining, sample()[1..].to_vec());
+
+        assert_eq!(purge_file(&path, 300).unwrap(), 1);
+        assert_eq!(load_from(&path).unwrap(), sample()[2..].to_vec());
+        assert_eq!(purge_file(&path, 300).unwrap(), 0);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_purge_keeps_concurrent_appends() {
+        let dir = std::env::temp_dir().join("qmpo_test_history_concurrent");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("history.jsonl");
+        for ts in 0..50 {
+            append_to(&path, &entry(ts, "directory:///old", Outcome::Ok)).unwrap();
+        }
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for i in 0..25 {
+                        let uri = format!("directory:///new/{thread}/{i}");
+                        append_to(path, &entry(1000, &uri, Outcome::Ok)).unwrap();
+                    }
+                });
+            }
+            scope.spawn(|| {
+                for _ in 0..25 {
+                    purge_file(&path, 100).unwrap();
+                }
+            });
+        });
+        purge_file(&path, 100).unwrap();
+        assert_eq!(load_from(&path).unwrap().len(), 100);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
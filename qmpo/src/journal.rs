@@ -0,0 +1,213 @@
+//! System log backends: native journald entries and RFC 5424 syslog.
+//!
+//! Both are sent as single datagrams over local Unix sockets, so a message is
+//! either delivered whole or not at all and the caller can fall back to the
+//! log file.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use crate::log::{self, Level};
+
+/// journald's native protocol socket.
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The local syslog socket.
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+const IDENTIFIER: &str = "qmpo";
+
+/// syslog facility `user`.
+const FACILITY_USER: u8 = 1;
+
+/// Syslog severity for a log level (RFC 5424 section 6.2.1).
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Send a journald entry. Each field becomes `QMPO_<KEY>` (e.g. `QMPO_URI`).
+pub fn send_journald(
+    socket: &Path,
+    level: Level,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> io::Result<()> {
+    UnixDatagram::unbound()?.send_to(&journald_entry(level, message, fields), socket)?;
+    Ok(())
+}
+
+/// Encode an entry in journald's native protocol.
+fn journald_entry(level: Level, message: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    push_field(&mut entry, "MESSAGE", message);
+    push_field(&mut entry, "PRIORITY", &severity(level).to_string());
+    push_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    for (key, value) in fields {
+        push_field(&mut entry, &field_name(key), value);
+    }
+    entry
+}
+
+/// Append `KEY=value\n`, or the length-prefixed binary form if `value`
+/// contains a newline.
+fn push_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Journal field names allow only uppercase letters, digits and underscores.
+fn field_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("QMPO_{key}")
+}
+
+/// Send an RFC 5424 syslog message with the fields appended as `key=value`.
+pub fn send_syslog(
+    socket: &Path,
+    timestamp: &str,
+    level: Level,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> io::Result<()> {
+    let line = syslog_message(timestamp, level, message, fields);
+    UnixDatagram::unbound()?.send_to(line.as_bytes(), socket)?;
+    Ok(())
+}
+
+/// Format `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG`.
+///
+/// The hostname is left as `-` for the local syslog daemon to fill in.
+fn syslog_message(timestamp: &str, level: Level, message: &str, fields: &[(&str, &str)]) -> String {
+    let pri = FACILITY_USER * 8 + severity(level);
+    let mut line = format!(
+        "<{pri}>1 {timestamp} - {IDENTIFIER} {} - - {message}",
+        std::process::id()
+    );
+    for (key, value) in fields {
+        line.push_str(&format!(" {key}={}", log::quote(value)));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    /// Decode a native protocol datagram into its fields.
+    fn parse_entry(mut data: &[u8]) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        while !data.is_empty() {
+            let end = data.iter().position(|&b| b == b'=' || b == b'\n').unwrap();
+            let key = String::from_utf8(data[..end].to_vec()).unwrap();
+            let value;
+            if data[end] == b'=' {
+                let len = data[end + 1..].iter().position(|&b| b == b'\n').unwrap();
+                value = &data[end + 1..end + 1 + len];
+                data = &data[end + 2 + len..];
+            } else {
+                let len = u64::from_le_bytes(data[end + 1..end + 9].try_into().unwrap()) as usize;
+                value = &data[end + 9..end + 9 + len];
+                assert_eq!(data[end + 9 + len], b'\n');
+                data = &data[end + 10 + len..];
+            }
+            fields.insert(key, String::from_utf8(value.to_vec()).unwrap());
+        }
+        fields
+    }
+
+    fn bind(name: &str) -> (std::path::PathBuf, UnixDatagram) {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        (dir, socket)
+    }
+
+    #[test]
+    fn test_field_name() {
+        assert_eq!(field_name("uri"), "QMPO_URI");
+        assert_eq!(field_name("x-qmpo.token"), "QMPO_X_QMPO_TOKEN");
+    }
+
+    #[test]
+    fn test_journald_entry_over_socket() {
+        let (dir, socket) = bind("qmpo_test_journald");
+        send_journald(
+            &dir.join("socket"),
+            Level::Error,
+            "Failed",
+            &[
+                ("uri", "directory:///a b"),
+                ("result", "failed"),
+                ("error", "first line\nsecond line"),
+            ],
+        )
+        .unwrap();
+
+        let mut buf = vec![0; 4096];
+        let len = socket.recv(&mut buf).unwrap();
+        let fields = parse_entry(&buf[..len]);
+        assert_eq!(fields["MESSAGE"], "Failed");
+        assert_eq!(fields["PRIORITY"], "3");
+        assert_eq!(fields["SYSLOG_IDENTIFIER"], "qmpo");
+        assert_eq!(fields["QMPO_URI"], "directory:///a b");
+        assert_eq!(fields["QMPO_RESULT"], "failed");
+        assert_eq!(fields["QMPO_ERROR"], "first line\nsecond line");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_syslog_message_over_socket() {
+        let (dir, socket) = bind("qmpo_test_syslog");
+        send_syslog(
+            &dir.join("socket"),
+            "2026-01-01T00:00:00Z",
+            Level::Info,
+            "Opening",
+            &[("path", "/srv/a b")],
+        )
+        .unwrap();
+
+        let mut buf = vec![0; 4096];
+        let len = socket.recv(&mut buf).unwrap();
+        let line = String::from_utf8(buf[..len].to_vec()).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                r#"<14>1 2026-01-01T00:00:00Z - qmpo {} - - Opening path="/srv/a b""#,
+                std::process::id()
+            )
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_missing_socket() {
+        let path = std::env::temp_dir().join("qmpo_test_no_such_socket");
+        assert!(send_journald(&path, Level::Info, "x", &[]).is_err());
+    }
+}
@@ -0,0 +1,534 @@
+//! File manager integration.
+//!
+//! Opens a path in the platform's file manager, selecting it when it is a file.
+//! [`plan`] decides the exact commands to run, so `qmpo resolve` can show them
+//! without launching anything; [`launch`] runs them.
+//!
+//! On Linux, [`Backend`] chooses how the file manager is reached.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::Path;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::error::{QmpoError, Result};
+use crate::history::Action;
+#[cfg(target_os = "linux")]
+use crate::portal;
+#[cfg(target_os = "windows")]
+use crate::winpath;
+#[cfg(target_os = "linux")]
+use crate::wsl::{self, Wsl};
+use crate::{activation, child};
+
+/// How the file manager is reached on Linux. Other platforms ignore this.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// FileManager1 over D-Bus, falling back to `xdg-open`.
+    #[default]
+    Auto,
+    /// FileManager1 over D-Bus only, so the file manager is started by the
+    /// session bus instead of as a child of qmpo.
+    Dbus,
+    /// The XDG desktop portal, which reaches the host from Flatpak and Snap.
+    Portal,
+    /// Windows Explorer, started through WSL interop.
+    Explorer,
+}
+
+/// Settings for the `[launcher]` config section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LauncherConfig {
+    pub backend: Backend,
+}
+
+/// The backend to use.
+///
+/// `auto` picks the portal inside Flatpak or Snap. Otherwise hardened mode
+/// uses D-Bus, since spawned programs would inherit the sandbox, and WSL with
+/// interop uses Explorer.
+pub fn backend(config: &Config) -> Backend {
+    match config.launcher.backend {
+        Backend::Auto if portal_available() => Backend::Portal,
+        Backend::Auto if config.sandbox.enabled => Backend::Dbus,
+        Backend::Auto if interop_available() => Backend::Explorer,
+        backend => backend,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn portal_available() -> bool {
+    portal::sandboxed()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn portal_available() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn interop_available() -> bool {
+    wsl::current().is_some_and(|wsl| wsl.interop)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interop_available() -> bool {
+    false
+}
+
+/// A command to run, as an argument vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub argv: Vec<OsString>,
+    /// Wait for the command and fall through to the next step if it fails.
+    /// Otherwise the command is spawned and left running.
+    pub wait: bool,
+    /// The last argument is `string:<startup id>`, filled in with the
+    /// activation token at launch.
+    pub startup_id: bool,
+    /// A portal request to make instead of running `argv`, which describes it.
+    #[cfg(target_os = "linux")]
+    pub portal: Option<(portal::Method, PathBuf)>,
+}
+
+impl Step {
+    fn spawn<I, S>(argv: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Self {
+            argv: argv.into_iter().map(|a| a.as_ref().to_owned()).collect(),
+            wait: false,
+            startup_id: false,
+            #[cfg(target_os = "linux")]
+            portal: None,
+        }
+    }
+
+    /// A portal request for `path`.
+    #[cfg(target_os = "linux")]
+    fn portal(method: portal::Method, path: &Path) -> Self {
+        Self {
+            portal: Some((method, path.to_path_buf())),
+            ..Self::spawn([OsString::from(method.qualified_name()), path.into()])
+        }
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn try_run<I, S>(argv: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Self {
+            wait: true,
+            ..Self::spawn(argv)
+        }
+    }
+
+    /// The argument vector with the activation token filled in.
+    fn argv_with_token(&self, token: Option<&str>) -> Vec<OsString> {
+        let mut argv = self.argv.clone();
+        if let (true, Some(token), Some(last)) = (self.startup_id, token, argv.last_mut()) {
+            *last = format!("string:{token}").into();
+        }
+        argv
+    }
+
+    fn run(&self, token: Option<&str>) -> Result<bool> {
+        #[cfg(target_os = "linux")]
+        if let Some((method, path)) = &self.portal {
+            portal::open(*method, path, token)?;
+            return Ok(true);
+        }
+        let argv = self.argv_with_token(token);
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| QmpoError::LaunchFailed("empty command".to_string()))?;
+        let command = |detach| {
+            let mut command = child::command(program, args, detach);
+            if let Some(token) = token {
+                command
+                    .env("XDG_ACTIVATION_TOKEN", token)
+                    .env("DESKTOP_STARTUP_ID", token);
+            }
+            command
+        };
+        if self.wait {
+            // A missing helper is not fatal; the next step is the fallback
+            let status = command(false).status();
+            return Ok(status.is_ok_and(|s| s.success()));
+        }
+        let mut child = command(true)
+            .spawn()
+            .map_err(|e| QmpoError::LaunchFailed(format!("{}: {e}", program.to_string_lossy())))?;
+        // Reap the child so `qmpo serve` and `qmpo daemon` do not collect zombies
+        std::thread::spawn(move || child.wait());
+        Ok(true)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self.argv.iter().map(|a| shell_quote(a)).collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// Quote an argument for display when it contains shell metacharacters.
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+    if plain {
+        arg.into_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// The commands that open a path, tried in order until one succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+/// Run a plan, passing an activation token so the file manager gets focus.
+pub fn launch(plan: &Plan) -> Result<()> {
+    let token = activation::take();
+    for step in &plan.steps {
+        if step.run(token.as_deref())? {
+            return Ok(());
+        }
+    }
+    Err(QmpoError::LaunchFailed("no launcher succeeded".to_string()))
+}
+
+/// Plan opening a path in Explorer, selecting it when revealing a file.
+#[cfg(target_os = "windows")]
+pub fn plan(path: &Path, action: Action, backend: Backend) -> Result<Plan> {
+    match action {
+        Action::Reveal => plan_reveal(&[path], backend),
+        Action::Open => Ok(Plan {
+            steps: vec![Step::spawn([
+                OsStr::new("explorer.exe"),
+                explorer_path(path).as_os_str(),
+            ])],
+        }),
+    }
+}
+
+/// Plan revealing files that share a parent directory.
+///
+/// `explorer.exe /select` takes a single item, so only the first file is selected.
+#[cfg(target_os = "windows")]
+pub fn plan_reveal(files: &[&Path], _backend: Backend) -> Result<Plan> {
+    let first = files
+        .first()
+        .ok_or_else(|| QmpoError::LaunchFailed("nothing to reveal".to_string()))?;
+    // Open parent directory with file selected
+    let mut arg = OsString::from("/select,");
+    arg.push(explorer_path(first));
+    Ok(Plan {
+        steps: vec![Step::spawn([OsString::from("explorer.exe"), arg])],
+    })
+}
+
+/// The path as Explorer understands it.
+///
+/// `canonicalize()` returns `\\?\` paths, which `explorer.exe /select,` mishandles.
+#[cfg(target_os = "windows")]
+fn explorer_path(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(s) => PathBuf::from(winpath::simplify(s).as_ref()),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Plan opening a path in Finder, selecting it when revealing a file.
+#[cfg(target_os = "macos")]
+pub fn plan(path: &Path, action: Action, backend: Backend) -> Result<Plan> {
+    match action {
+        Action::Reveal => plan_reveal(&[path], backend),
+        Action::Open => Ok(Plan {
+            steps: vec![Step::spawn([OsStr::new("open"), path.as_os_str()])],
+        }),
+    }
+}
+
+/// Plan revealing files that share a parent directory in one Finder window.
+#[cfg(target_os = "macos")]
+pub fn plan_reveal(files: &[&Path], _backend: Backend) -> Result<Plan> {
+    // Open parent directory with files selected using -R flag
+    let argv = [OsStr::new("open"), OsStr::new("-R")]
+        .into_iter()
+        .chain(files.iter().map(|file| file.as_os_str()));
+    Ok(Plan {
+        steps: vec![Step::spawn(argv)],
+    })
+}
+
+/// Plan opening a path in the desktop's file manager.
+/// Revealing a file asks the file manager to select it, or opens its parent directory.
+#[cfg(target_os = "linux")]
+pub fn plan(path: &Path, action: Action, backend: Backend) -> Result<Plan> {
+    match (action, backend) {
+        (Action::Reveal, _) => plan_reveal(&[path], backend),
+        (Action::Open, Backend::Explorer) => Ok(Plan {
+            steps: vec![explorer(wsl::current(), path, false)?],
+        }),
+        (Action::Open, Backend::Portal) => Ok(Plan {
+            steps: vec![Step::portal(portal::Method::OpenFile, path)],
+        }),
+        (Action::Open, Backend::Auto) => Ok(Plan {
+            steps: vec![Step::spawn([OsStr::new("xdg-open"), path.as_os_str()])],
+        }),
+        (Action::Open, Backend::Dbus) => Ok(Plan {
+            steps: vec![file_manager1("ShowFolders", &[path])],
+        }),
+    }
+}
+
+/// Plan revealing files that share a parent directory with a single ShowItems call.
+///
+/// The portal and Explorer select a single item, so only the first file is
+/// selected there.
+#[cfg(target_os = "linux")]
+pub fn plan_reveal(files: &[&Path], backend: Backend) -> Result<Plan> {
+    let first = files
+        .first()
+        .ok_or_else(|| QmpoError::LaunchFailed("nothing to reveal".to_string()))?;
+    match backend {
+        Backend::Portal => {
+            return Ok(Plan {
+                steps: vec![Step::portal(portal::Method::OpenDirectory, first)],
+            });
+        }
+        Backend::Explorer => {
+            return Ok(Plan {
+                steps: vec![explorer(wsl::current(), first, true)?],
+            });
+        }
+        Backend::Auto | Backend::Dbus => {}
+    }
+
+    // Try dbus-send to select files in file manager (works with Nautilus, Dolphin, etc.)
+    let mut steps = vec![file_manager1("ShowItems", files)];
+    // Fallback: open parent directory without file selection
+    if let (Backend::Auto, Some(parent)) = (backend, first.parent()) {
+        steps.push(Step::spawn([OsStr::new("xdg-open"), parent.as_os_str()]));
+    }
+    Ok(Plan { steps })
+}
+
+/// Start Explorer through WSL interop on the Windows path of `path`,
+/// selecting it if `select` is set.
+#[cfg(target_os = "linux")]
+fn explorer(wsl: Option<&Wsl>, path: &Path, select: bool) -> Result<Step> {
+    let wsl = wsl.ok_or_else(|| QmpoError::LaunchFailed("not running in WSL".to_string()))?;
+    let windows = wsl.to_windows(path).ok_or_else(|| {
+        QmpoError::LaunchFailed(format!("no Windows path for {}", path.display()))
+    })?;
+    let arg = if select {
+        format!("/select,{windows}")
+    } else {
+        windows
+    };
+    Ok(Step::spawn(["explorer.exe", &arg]))
+}
+
+/// A `dbus-send` call to an `org.freedesktop.FileManager1` method taking URIs.
+#[cfg(target_os = "linux")]
+fn file_manager1(method: &str, paths: &[&Path]) -> Step {
+    let uris: Vec<String> = paths.iter().map(|path| file_uri(path)).collect();
+    let step = Step::try_run([
+        "dbus-send",
+        "--session",
+        "--dest=org.freedesktop.FileManager1",
+        "--type=method_call",
+        "/org/freedesktop/FileManager1",
+        &format!("org.freedesktop.FileManager1.{method}"),
+        &format!("array:string:{}", uris.join(",")),
+        "string:",
+    ]);
+    Step {
+        startup_id: true,
+        ..step
+    }
+}
+
+/// Characters escaped in `file://` URIs; `,` separates items in dbus-send arrays.
+#[cfg(target_os = "linux")]
+const FILE_URI_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b',')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Build a `file://` URI for an absolute path.
+#[cfg(target_os = "linux")]
+fn file_uri(path: &Path) -> String {
+    let encoded =
+        percent_encoding::percent_encode(path.as_os_str().as_encoded_bytes(), FILE_URI_ENCODE_SET);
+    format!("file://{encoded}")
+}
+
+/// No file manager integration exists for this platform.
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+pub fn plan(_path: &Path, _action: Action, backend: Backend) -> Result<Plan> {
+    plan_reveal(&[], backend)
+}
+
+/// No file manager integration exists for this platform.
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+pub fn plan_reveal(_files: &[&Path], _backend: Backend) -> Result<Plan> {
+    Err(QmpoError::LaunchFailed(
+        "unsupported operating system".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_display_quotes() {
+        let step = Step::spawn(["xdg-open", "/srv/My Files/it's"]);
+        assert_eq!(step.to_string(), r"xdg-open '/srv/My Files/it'\''s'");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_plans() {
+        let open = plan(Path::new("/srv/share"), Action::Open, Backend::Auto).unwrap();
+        assert_eq!(open.steps.len(), 1);
+        assert_eq!(open.steps[0].to_string(), "xdg-open /srv/share");
+
+        let reveal = plan(Path::new("/srv/share/a.txt"), Action::Reveal, Backend::Auto).unwrap();
+        assert_eq!(reveal.steps.len(), 2);
+        assert!(reveal.steps[0].wait);
+        assert!(
+            reveal.steps[0]
+                .to_string()
+                .contains("array:string:file:///srv/share/a.txt")
+        );
+        assert_eq!(reveal.steps[1].to_string(), "xdg-open /srv/share");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_reveal_group() {
+        let plan = plan_reveal(
+            &[Path::new("/srv/a,b.txt"), Path::new("/srv/c d.txt")],
+            Backend::Auto,
+        )
+        .unwrap();
+        assert!(
+            plan.steps[0]
+                .to_string()
+                .contains("array:string:file:///srv/a%2Cb.txt,file:///srv/c%20d.txt")
+        );
+        assert_eq!(plan.steps[1].to_string(), "xdg-open /srv");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_dbus_backend_never_spawns() {
+        let open = plan(Path::new("/srv/share"), Action::Open, Backend::Dbus).unwrap();
+        assert_eq!(open.steps.len(), 1);
+        assert!(
+            open.steps[0]
+                .to_string()
+                .contains("FileManager1.ShowFolders array:string:file:///srv/share")
+        );
+
+        let reveal = plan(Path::new("/srv/share/a.txt"), Action::Reveal, Backend::Dbus).unwrap();
+        assert_eq!(reveal.steps.len(), 1);
+        assert!(reveal.steps[0].to_string().contains("ShowItems"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_portal_backend() {
+        let open = plan(Path::new("/srv/share"), Action::Open, Backend::Portal).unwrap();
+        assert_eq!(
+            open.steps[0].to_string(),
+            "org.freedesktop.portal.OpenURI.OpenFile /srv/share"
+        );
+        let reveal = plan_reveal(
+            &[Path::new("/srv/a.txt"), Path::new("/srv/b.txt")],
+            Backend::Portal,
+        )
+        .unwrap();
+        assert_eq!(reveal.steps.len(), 1);
+        assert_eq!(
+            reveal.steps[0].portal,
+            Some((portal::Method::OpenDirectory, PathBuf::from("/srv/a.txt")))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_wsl_explorer() {
+        let wsl = Wsl {
+            distro: Some("Ubuntu".to_string()),
+            interop: true,
+            mount_root: PathBuf::from("/mnt"),
+        };
+        let step = explorer(Some(&wsl), Path::new("/mnt/c/Users/me"), false).unwrap();
+        assert_eq!(step.argv, ["explorer.exe", r"C:\Users\me"]);
+        assert!(!step.wait);
+        let step = explorer(Some(&wsl), Path::new("/home/me/a.txt"), true).unwrap();
+        assert_eq!(
+            step.argv,
+            [
+                "explorer.exe",
+                r"/select,\\wsl.localhost\Ubuntu\home\me\a.txt"
+            ]
+        );
+
+        let err = explorer(None, Path::new("/home/me"), false).unwrap_err();
+        assert!(err.to_string().contains("not running in WSL"));
+        let wsl = Wsl {
+            distro: None,
+            ..wsl
+        };
+        assert!(explorer(Some(&wsl), Path::new("/home/me"), false).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_startup_id_filled_in() {
+        let reveal = plan(Path::new("/srv/a.txt"), Action::Reveal, Backend::Auto).unwrap();
+        let argv = reveal.steps[0].argv_with_token(Some("token-123"));
+        assert_eq!(argv.last().unwrap(), "string:token-123");
+        assert_eq!(reveal.steps[0].argv_with_token(None), reveal.steps[0].argv);
+        // Only FileManager1 calls take a startup ID argument
+        let fallback = &reveal.steps[1];
+        assert_eq!(fallback.argv_with_token(Some("token-123")), fallback.argv);
+    }
+
+    #[test]
+    fn test_launch_reports_missing_program() {
+        let plan = Plan {
+            steps: vec![Step::spawn(["/nonexistent/qmpo-test-launcher"])],
+        };
+        assert!(matches!(launch(&plan), Err(QmpoError::LaunchFailed(_))));
+    }
+}
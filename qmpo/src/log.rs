@@ -1,109 +1,516 @@
-//! Simple file-based logging for qmpo.
+//! Leveled, file-based logging for qmpo.
 //!
 //! Writes logs to `~/.local/share/qmpo/qmpo.log` (Linux),
 //! `~/Library/Application Support/qmpo/qmpo.log` (macOS),
 //! or `%APPDATA%\qmpo\qmpo.log` (Windows).
+//!
+//! The level, format (text or JSON lines), rotation and stderr mirroring are set
+//! in the `[log]` section of the config file; `QMPO_LOG` overrides the level.
+//! When the file exceeds `max_size` it is rotated to `qmpo.log.1`, `qmpo.log.2`, ...
+//! keeping `keep` generations. Rotation and appends hold a lock on `qmpo.log.lock`
+//! so concurrent invocations neither interleave nor race the rotation.
+//!
+//! On Linux, `backend` can send entries to journald or syslog instead; if the
+//! system logger cannot be reached, the entry is written to the file.
 
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::path::PathBuf;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
 use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::{privacy, time};
+
+/// Environment variable that overrides the configured level.
+const LEVEL_ENV: &str = "QMPO_LOG";
+
+static SETTINGS: RwLock<Option<LogConfig>> = RwLock::new(None);
+
+/// Log severity, from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" | "warning" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// Log line format.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `2026-01-01T12:00:00+09:00 [INFO] message key=value`
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Where log entries are sent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// `qmpo.log` in the data directory.
+    #[default]
+    File,
+    /// Native journald entries with `QMPO_*` fields, falling back to syslog.
+    Journald,
+    /// RFC 5424 messages on `/dev/log`.
+    Syslog,
+}
+
+/// Settings for the `[log]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Most verbose level written.
+    pub level: Level,
+    /// Destination of log entries.
+    pub backend: Backend,
+    /// Line format.
+    pub format: Format,
+    /// Size in bytes at which the log is rotated.
+    pub max_size: u64,
+    /// Number of rotated generations kept (0 deletes the log on rotation).
+    pub keep: usize,
+    /// Also write log lines to stderr when it is attached to a terminal.
+    pub stderr: bool,
+    /// Write timestamps in UTC instead of local time.
+    pub utc: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            backend: Backend::File,
+            format: Format::Text,
+            max_size: 1024 * 1024, // 1MB
+            keep: 3,
+            stderr: false,
+            utc: false,
+        }
+    }
+}
+
+/// Install the logging settings. `QMPO_LOG` takes precedence over `config.level`.
+///
+/// Until this is called (or if the config failed to load), defaults apply.
+/// Calling it again replaces the settings (e.g. when `qmpo daemon` reloads).
+pub fn init(config: &LogConfig) {
+    *SETTINGS.write().unwrap_or_else(|e| e.into_inner()) = Some(with_env_level(config.clone()));
+}
+
+fn settings() -> LogConfig {
+    SETTINGS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| with_env_level(LogConfig::default()))
+}
 
-const MAX_LOG_SIZE: u64 = 1024 * 1024; // 1MB
+fn with_env_level(mut config: LogConfig) -> LogConfig {
+    if let Some(level) = std::env::var(LEVEL_ENV)
+        .ok()
+        .as_deref()
+        .and_then(Level::parse)
+    {
+        config.level = level;
+    }
+    config
+}
 
 /// Get the log file path.
 fn log_path() -> Option<PathBuf> {
     ProjectDirs::from("", "", "qmpo").map(|dirs| dirs.data_dir().join("qmpo.log"))
 }
 
-/// Write a log entry. Silently fails if logging is not possible.
-pub fn log(level: &str, message: &str) {
-    let Some(path) = log_path() else {
+/// Write a log entry with structured fields. Silently fails if logging is not possible.
+pub fn log(level: Level, message: &str, fields: &[(&str, &str)]) {
+    let settings = settings();
+    if level > settings.level {
+        return;
+    }
+
+    // Redact tracked paths before the entry reaches any backend
+    let message = privacy::scrub(message);
+    let values: Vec<String> = fields.iter().map(|(_, v)| privacy::scrub(v)).collect();
+    let fields: Vec<(&str, &str)> = fields
+        .iter()
+        .zip(&values)
+        .map(|((key, _), value)| (*key, value.as_str()))
+        .collect();
+
+    let timestamp = time::format_timestamp(time::now(), settings.utc);
+    let line = format_line(settings.format, &timestamp, level, &message, &fields);
+
+    if settings.stderr && io::stderr().is_terminal() {
+        eprintln!("{line}");
+    }
+
+    if send_to_system_log(settings.backend, &timestamp, level, &message, &fields) {
         return;
-    };
+    }
+
+    if let Some(path) = log_path() {
+        let _ = write_line(&path, &line, &settings);
+    }
+}
+
+/// Send an entry to journald or syslog. Returns false if it was not delivered.
+#[cfg(unix)]
+fn send_to_system_log(
+    backend: Backend,
+    timestamp: &str,
+    level: Level,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> bool {
+    use crate::journal::{self, JOURNALD_SOCKET, SYSLOG_SOCKET};
+
+    let syslog =
+        || journal::send_syslog(Path::new(SYSLOG_SOCKET), timestamp, level, message, fields);
+    match backend {
+        Backend::File => false,
+        Backend::Journald => {
+            journal::send_journald(Path::new(JOURNALD_SOCKET), level, message, fields)
+                .or_else(|_| syslog())
+                .is_ok()
+        }
+        Backend::Syslog => syslog().is_ok(),
+    }
+}
+
+/// Send an entry to journald or syslog. Returns false if it was not delivered.
+#[cfg(not(unix))]
+fn send_to_system_log(
+    _backend: Backend,
+    _timestamp: &str,
+    _level: Level,
+    _message: &str,
+    _fields: &[(&str, &str)],
+) -> bool {
+    false
+}
+
+fn format_line(
+    format: Format,
+    timestamp: &str,
+    level: Level,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> String {
+    match format {
+        Format::Text => {
+            let mut line = format!("{} [{}] {}", timestamp, level.as_str(), message);
+            for (key, value) in fields {
+                let _ = write!(line, " {}={}", key, quote(value));
+            }
+            line
+        }
+        Format::Json => {
+            let mut object = serde_json::Map::new();
+            object.insert("ts".into(), timestamp.into());
+            object.insert("level".into(), level.as_str().to_ascii_lowercase().into());
+            object.insert("msg".into(), message.into());
+            for (key, value) in fields {
+                object.insert((*key).into(), (*value).into());
+            }
+            serde_json::Value::Object(object).to_string()
+        }
+    }
+}
+
+/// Quote a text-format field value if it contains spaces, quotes or is empty.
+pub fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains([' ', '"', '=', '\n', '\t']) {
+        return value.to_string();
+    }
+    serde_json::Value::from(value).to_string()
+}
 
+fn write_line(path: &Path, line: &str, settings: &LogConfig) -> io::Result<()> {
     // Ensure parent directory exists
     if let Some(parent) = path.parent() {
-        let _ = fs::create_dir_all(parent);
+        fs::create_dir_all(parent)?;
     }
 
+    let lock = File::create(path.with_extension("log.lock"))?;
+    lock.lock()?;
+
     // Rotate log if too large
-    if fs::metadata(&path).is_ok_and(|m| m.len() > MAX_LOG_SIZE) {
-        let _ = fs::remove_file(&path);
+    if fs::metadata(path).is_ok_and(|m| m.len() > settings.max_size) {
+        rotate(path, settings.keep)?;
     }
 
     // Append to log file
-    let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&path) else {
-        return;
-    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{line}\n").as_bytes())
+    // The lock is released when `lock` is dropped
+}
+
+/// Shift `qmpo.log` to `qmpo.log.1`, `qmpo.log.1` to `qmpo.log.2`, and so on,
+/// dropping generations beyond `keep`.
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
 
-    let timestamp = chrono_lite_timestamp();
-    let _ = writeln!(file, "{} [{}] {}", timestamp, level, message);
+    let _ = fs::remove_file(generation(path, keep));
+    for n in (1..keep).rev() {
+        let from = generation(path, n);
+        if from.exists() {
+            fs::rename(&from, generation(path, n + 1))?;
+        }
+    }
+    fs::rename(path, generation(path, 1))
 }
 
-/// Simple timestamp without external chrono dependency.
-fn chrono_lite_timestamp() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
+/// Path of rotated generation `n` (e.g. `qmpo.log.2`).
+fn generation(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
 
-    let duration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default();
-    let secs = duration.as_secs();
+/// Remove log lines written before `before` (seconds since the Unix epoch)
+/// from `qmpo.log` and its rotated generations. Returns the number of lines removed.
+///
+/// Entries already sent to journald or syslog are not affected.
+pub fn purge(before: u64) -> io::Result<usize> {
+    match log_path() {
+        Some(path) => purge_file(&path, before),
+        None => Ok(0),
+    }
+}
 
-    // Simple UTC timestamp calculation
-    let days = secs / 86400;
-    let time_of_day = secs % 86400;
-    let hours = time_of_day / 3600;
-    let minutes = (time_of_day % 3600) / 60;
-    let seconds = time_of_day % 60;
+fn purge_file(path: &Path, before: u64) -> io::Result<usize> {
+    if !path.parent().is_some_and(Path::exists) {
+        return Ok(0);
+    }
 
-    // Approximate date calculation (not accounting for leap years perfectly, but good enough for logs)
-    let mut year = 1970;
-    let mut remaining_days = days;
+    let lock = File::create(path.with_extension("log.lock"))?;
+    lock.lock()?;
 
-    loop {
-        let days_in_year = if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) {
-            366
-        } else {
-            365
+    let mut removed = 0;
+    let files = std::iter::once(path.to_path_buf()).chain(
+        (1..)
+            .map(|n| generation(path, n))
+            .take_while(|p| p.exists()),
+    );
+    for file in files.collect::<Vec<_>>() {
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
         };
-        if remaining_days < days_in_year {
-            break;
-        }
-        remaining_days -= days_in_year;
-        year += 1;
-    }
 
-    let is_leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
-    let days_in_months: [u64; 12] = if is_leap {
-        [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    } else {
-        [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    };
+        // Lines without a recognizable timestamp are kept
+        let (old, kept): (Vec<&str>, Vec<&str>) = text
+            .lines()
+            .partition(|line| line_timestamp(line).is_some_and(|ts| ts < before));
+        if old.is_empty() {
+            continue;
+        }
+        removed += old.len();
 
-    let mut month = 1;
-    for &days_in_month in &days_in_months {
-        if remaining_days < days_in_month {
-            break;
+        if kept.is_empty() {
+            fs::remove_file(&file)?;
+        } else {
+            let tmp = file.with_extension("purge.tmp");
+            fs::write(&tmp, kept.join("\n") + "\n")?;
+            fs::rename(&tmp, &file)?;
         }
-        remaining_days -= days_in_month;
-        month += 1;
     }
-    let day = remaining_days + 1;
+    Ok(removed)
+}
 
-    format!(
-        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-        year, month, day, hours, minutes, seconds
-    )
+/// Timestamp of a text or JSON log line.
+fn line_timestamp(line: &str) -> Option<u64> {
+    if line.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(line).ok()?;
+        return time::parse_timestamp(value["ts"].as_str()?);
+    }
+    // Older text logs used `YYYY-MM-DD HH:MM:SS`, which contains a space
+    time::parse_timestamp(line.split(' ').next()?)
+        .or_else(|| time::parse_timestamp(line.get(..19)?))
+}
+
+/// Log an error message.
+pub fn error(message: &str) {
+    log(Level::Error, message, &[]);
 }
 
 /// Log an info message.
 pub fn info(message: &str) {
-    log("INFO", message);
+    log(Level::Info, message, &[]);
 }
 
-/// Log an error message.
-pub fn error(message: &str) {
-    log("ERROR", message);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_order() {
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Info < Level::Debug);
+        assert!(Level::Debug < Level::Trace);
+    }
+
+    #[test]
+    fn test_level_parse() {
+        assert_eq!(Level::parse("DEBUG"), Some(Level::Debug));
+        assert_eq!(Level::parse(" warning "), Some(Level::Warn));
+        assert_eq!(Level::parse("loud"), None);
+    }
+
+    #[test]
+    fn test_text_format() {
+        let line = format_line(
+            Format::Text,
+            "2026-01-01T00:00:00Z",
+            Level::Info,
+            "Received URI",
+            &[("uri", "directory:///a b"), ("origin", "cli")],
+        );
+        assert_eq!(
+            line,
+            r#"2026-01-01T00:00:00Z [INFO] Received URI uri="directory:///a b" origin=cli"#
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = format_line(
+            Format::Json,
+            "2026-01-01T00:00:00Z",
+            Level::Error,
+            "Failed",
+            &[("error", "Path does not exist: /a")],
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "error");
+        assert_eq!(value["msg"], "Failed");
+        assert_eq!(value["error"], "Path does not exist: /a");
+    }
+
+    #[test]
+    fn test_line_timestamp() {
+        assert_eq!(
+            line_timestamp("1970-01-01T00:01:00Z [INFO] Opening path=/a"),
+            Some(60)
+        );
+        assert_eq!(
+            line_timestamp("1970-01-01 00:01:00 [INFO] Opening path=/a"),
+            Some(60)
+        );
+        assert_eq!(
+            line_timestamp(r#"{"ts":"1970-01-01T09:01:00+09:00","level":"info","msg":"x"}"#),
+            Some(60)
+        );
+        assert_eq!(line_timestamp("garbage"), None);
+    }
+
+    #[test]
+    fn test_purge_across_generations() {
+        let dir = std::env::temp_dir().join("qmpo_test_log_purge");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("qmpo.log");
+
+        fs::write(generation(&path, 2), "1970-01-01T00:00:10Z [INFO] a\n").unwrap();
+        fs::write(
+            generation(&path, 1),
+            "1970-01-01T00:00:20Z [INFO] b\n1970-01-01T00:01:40Z [INFO] c\n",
+        )
+        .unwrap();
+        fs::write(&path, "1970-01-01T00:02:00Z [INFO] d\nno timestamp\n").unwrap();
+
+        assert_eq!(purge_file(&path, 60).unwrap(), 2);
+        assert!(!generation(&path, 2).exists());
+        assert_eq!(
+            fs::read_to_string(generation(&path, 1)).unwrap(),
+            "1970-01-01T00:01:40Z [INFO] c\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1970-01-01T00:02:00Z [INFO] d\nno timestamp\n"
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation_keeps_generations() {
+        let dir = std::env::temp_dir().join("qmpo_test_log_rotation");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("qmpo.log");
+
+        let settings = LogConfig {
+            max_size: 10,
+            keep: 2,
+            ..LogConfig::default()
+        };
+        for i in 0..4 {
+            write_line(&path, &format!("line number {i}"), &settings).unwrap();
+        }
+
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(path.clone()), "line number 3\n");
+        assert_eq!(read(generation(&path, 1)), "line number 2\n");
+        assert_eq!(read(generation(&path, 2)), "line number 1\n");
+        assert!(!generation(&path, 3).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation_without_generations() {
+        let dir = std::env::temp_dir().join("qmpo_test_log_rotation_keep0");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("qmpo.log");
+
+        let settings = LogConfig {
+            max_size: 10,
+            keep: 0,
+            ..LogConfig::default()
+        };
+        write_line(&path, "first long line", &settings).unwrap();
+        write_line(&path, "second", &settings).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        assert!(!generation(&path, 1).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,224 @@
+//! Fallback lookups for names that differ from the filesystem's spelling.
+//!
+//! Links written on macOS often carry NFD-normalized names, and links from
+//! Windows users ignore case, while ext4 compares names byte for byte. When
+//! the exact path does not exist, [`find`] walks it component by component and
+//! accepts a directory entry that is equal after Unicode normalization and/or
+//! case folding, as enabled in `[lookup]`.
+//!
+//! A component that matches several entries (e.g. `Data` and `DATA`) is never
+//! guessed: the lookup reports the candidates and the path stays missing.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// Settings for the `[lookup]` config section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LookupConfig {
+    /// Match names that are equal after NFC normalization.
+    pub unicode: bool,
+    /// Match names that are equal ignoring case.
+    pub ignore_case: bool,
+}
+
+impl LookupConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.unicode || self.ignore_case
+    }
+
+    /// The form two names must share to be considered equal.
+    fn key(&self, name: &str) -> String {
+        let name = if self.ignore_case {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        };
+        if self.unicode {
+            name.nfc().collect()
+        } else {
+            name
+        }
+    }
+}
+
+/// The outcome of a fallback lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Every component matched; `substituted` is true if any was respelled.
+    Found { path: PathBuf, substituted: bool },
+    /// A component matched no entry.
+    Missing,
+    /// A component matched several entries of `dir`.
+    Ambiguous {
+        dir: PathBuf,
+        candidates: Vec<String>,
+    },
+}
+
+/// Look up `path`, respelling components that do not exist as given.
+///
+/// # Errors
+///
+/// Returns the error of a directory that exists but cannot be listed.
+pub fn find(path: &Path, config: &LookupConfig) -> io::Result<Lookup> {
+    let mut found = PathBuf::new();
+    let mut substituted = false;
+
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            found.push(component);
+            continue;
+        };
+        let exact = found.join(name);
+        if fs::symlink_metadata(&exact).is_ok() {
+            found = exact;
+            continue;
+        }
+        // Names that are not UTF-8 only match exactly
+        let Some(name) = name.to_str() else {
+            return Ok(Lookup::Missing);
+        };
+
+        let key = config.key(name);
+        let mut candidates = Vec::new();
+        for entry in fs::read_dir(&found)? {
+            let entry = entry?.file_name();
+            if entry.to_str().is_some_and(|entry| config.key(entry) == key) {
+                candidates.push(entry);
+            }
+        }
+        match candidates.as_slice() {
+            [] => return Ok(Lookup::Missing),
+            [entry] => {
+                found.push(entry);
+                substituted = true;
+            }
+            _ => {
+                let mut candidates: Vec<String> = candidates
+                    .iter()
+                    .map(|entry| entry.to_string_lossy().into_owned())
+                    .collect();
+                candidates.sort();
+                return Ok(Lookup::Ambiguous {
+                    dir: found,
+                    candidates,
+                });
+            }
+        }
+    }
+    Ok(Lookup::Found {
+        path: found,
+        substituted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: LookupConfig = LookupConfig {
+        unicode: true,
+        ignore_case: true,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("qmpo_test_lookup_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_nfd_link_finds_nfc_name() {
+        let dir = temp_dir("nfd");
+        // Stored as NFC: "Café" with U+00E9, and "ポスター" with precomposed ポ
+        fs::create_dir_all(
+            dir.join("Caf\u{e9}")
+                .join("\u{30dd}\u{30b9}\u{30bf}\u{30fc}"),
+        )
+        .unwrap();
+
+        // Linked as NFD: e + U+0301, ホ + U+309A
+        let link = dir
+            .join("Cafe\u{301}")
+            .join("\u{30db}\u{309a}\u{30b9}\u{30bf}\u{30fc}");
+        let unicode = LookupConfig {
+            unicode: true,
+            ignore_case: false,
+        };
+        assert_eq!(
+            find(&link, &unicode).unwrap(),
+            Lookup::Found {
+                path: dir
+                    .join("Caf\u{e9}")
+                    .join("\u{30dd}\u{30b9}\u{30bf}\u{30fc}"),
+                substituted: true
+            }
+        );
+        // Case folding alone does not normalize
+        let ignore_case = LookupConfig {
+            unicode: false,
+            ignore_case: true,
+        };
+        assert_eq!(find(&link, &ignore_case).unwrap(), Lookup::Missing);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_case_insensitive_match() {
+        let dir = temp_dir("case");
+        fs::create_dir_all(dir.join("Reports").join("Q1")).unwrap();
+        fs::write(dir.join("Reports").join("Q1").join("Summary.txt"), "").unwrap();
+
+        assert_eq!(
+            find(&dir.join("REPORTS/q1/summary.TXT"), &BOTH).unwrap(),
+            Lookup::Found {
+                path: dir.join("Reports/Q1/Summary.txt"),
+                substituted: true
+            }
+        );
+        assert_eq!(
+            find(&dir.join("Reports/Q2"), &BOTH).unwrap(),
+            Lookup::Missing
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ambiguous_match_refused() {
+        let dir = temp_dir("ambiguous");
+        fs::create_dir_all(dir.join("Data")).unwrap();
+        fs::create_dir_all(dir.join("DATA")).unwrap();
+        // Case-insensitive filesystems cannot hold both names
+        if fs::read_dir(&dir).unwrap().count() < 2 {
+            let _ = fs::remove_dir_all(&dir);
+            return;
+        }
+
+        assert_eq!(
+            find(&dir.join("data"), &BOTH).unwrap(),
+            Lookup::Ambiguous {
+                dir: dir.clone(),
+                candidates: vec!["DATA".to_string(), "Data".to_string()]
+            }
+        );
+        // An exact match is taken as is
+        assert_eq!(
+            find(&dir.join("Data"), &BOTH).unwrap(),
+            Lookup::Found {
+                path: dir.join("Data"),
+                substituted: false
+            }
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
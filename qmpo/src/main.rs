@@ -4,124 +4,635 @@
 
 #![windows_subsystem = "windows"]
 
+mod activation;
+mod child;
+mod config;
+#[cfg(unix)]
+mod daemon;
+mod debounce;
 mod error;
+mod history;
+#[cfg(unix)]
+mod journal;
+mod launcher;
 mod log;
+mod lookup;
+mod mapping;
+mod placeholder;
+#[cfg(target_os = "linux")]
+mod portal;
+mod privacy;
+mod probe;
+mod resolve;
+mod sandbox;
+mod secret;
+mod serve;
+mod signing;
+mod time;
 mod uri;
+mod webdav;
+mod winpath;
+#[cfg(target_os = "linux")]
+mod wsl;
+#[cfg(target_os = "linux")]
+mod x11;
 
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::Parser;
-use uri::DirectoryUri;
+use clap::{Parser, Subcommand};
+use config::Config;
+use error::QmpoError;
+use log::Level;
 
 #[derive(Parser, Debug)]
 #[command(name = "qmpo")]
 #[command(about = "Open Directory With Browser - directory:// URI handler")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// The directory URI to open (e.g., directory:///home/user)
-    uri: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The directory URIs to open (e.g., directory:///home/user)
+    #[arg(value_name = "URI", required_unless_present = "stdin")]
+    uris: Vec<String>,
+
+    /// Read URIs to open from standard input, one per line
+    #[arg(long)]
+    stdin: bool,
+
+    /// Print output and errors as JSON
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a local HTTP bridge on 127.0.0.1 for browsers without protocol handlers
+    Serve {
+        /// Port to listen on (overrides serve.port in the config file)
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Print the access token for POST /open and exit
+        #[arg(long)]
+        show_token: bool,
+    },
+    /// Print a signed, expiring directory:// link for a path
+    Sign {
+        /// The absolute path to link to
+        path: PathBuf,
+
+        /// How long the link stays valid (e.g., 30m, 12h, 7d, 2w)
+        #[arg(long, default_value = "7d", value_parser = time::parse_duration)]
+        ttl: Duration,
+    },
+    /// Show the history of opened URIs
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+
+        /// Only show entries newer than this (e.g., 30m, 12h, 7d)
+        #[arg(long, value_parser = time::parse_duration)]
+        since: Option<Duration>,
+
+        /// Only show failed entries
+        #[arg(long)]
+        failed: bool,
+    },
+    /// Show how a URI would be opened, without opening it
+    Resolve {
+        /// The directory URI to resolve
+        uri: String,
+    },
+    /// Keep running and open URIs forwarded by other qmpo processes
+    #[cfg(unix)]
+    Daemon,
+    /// Manage the log file
+    Log {
+        #[command(subcommand)]
+        command: LogCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// Open the URI of history entry N again (1 = most recent)
+    Reopen {
+        /// Entry number as shown by `qmpo history`
+        n: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LogCommand {
+    /// Delete log lines and history entries older than a given age
+    Purge {
+        /// Age beyond which entries are deleted (e.g., 30d, 12w)
+        #[arg(long, value_parser = time::parse_duration)]
+        older_than: Duration,
+    },
 }
 
 fn main() {
     let args = Args::parse();
+    let json = args.json;
 
-    log::info(&format!("Received URI: {}", args.uri));
+    if let Err(e) = dispatch(args) {
+        // The open pipeline logs its own failures
+        let logged = e.downcast_ref::<Logged>();
+        if logged.is_none() {
+            log::log(
+                Level::Error,
+                "Failed",
+                &[("result", "failed"), ("error", &e.to_string())],
+            );
+        }
 
-    if let Err(e) = run(&args.uri) {
-        log::error(&format!("Failed: {}", e));
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+        // Errors outside the open pipeline (e.g. bad arguments to a subcommand) use 1
+        let typed = logged
+            .map(|Logged(e)| e)
+            .or_else(|| e.downcast_ref::<QmpoError>());
+        let code = typed.map_or(1, QmpoError::exit_code);
+        if json {
+            let error = serde_json::json!({
+                "ok": false,
+                "error": e.to_string(),
+                "kind": typed.map_or("other", QmpoError::kind),
+                "code": code,
+            });
+            println!("{error}");
+        } else {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(code);
     }
+}
+
+/// An error the open pipeline has already logged.
+#[derive(Debug)]
+struct Logged(QmpoError);
 
-    log::info("Completed successfully");
+impl std::fmt::Display for Logged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
-fn run(uri_str: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let uri = DirectoryUri::parse(uri_str)?;
-    let path = uri.path();
+impl std::error::Error for Logged {}
+
+/// Load the configuration and run the requested command.
+///
+/// URIs go to a running `qmpo daemon` before anything is loaded; the daemon
+/// opens them with its own configuration.
+fn dispatch(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    activation::init_from_env();
+    if args.command.is_none() {
+        activation::request();
+    }
+    let mut uris = args.uris;
+    if args.command.is_none() && args.stdin {
+        for line in std::io::stdin().lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                uris.push(line.trim().to_string());
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if args.command.is_none()
+        && !uris.is_empty()
+        && let Some(results) = daemon::forward(&uris)
+    {
+        return report(&uris, results, args.json);
+    }
 
-    log::info(&format!("Parsed path: {}", path.display()));
+    let config = Config::load()?;
+    log::init(&config.log);
+    privacy::init(&config.privacy)?;
 
-    if !path.exists() {
-        return Err(format!("Path does not exist: {}", path.display()).into());
+    match (args.command, uris) {
+        (Some(Command::Serve { port, show_token }), _) => serve(config, port, show_token),
+        (Some(Command::Sign { path, ttl }), _) => sign(&config, &path, ttl),
+        (
+            Some(Command::History {
+                command,
+                since,
+                failed,
+            }),
+            _,
+        ) => history(&config, command, since, failed, args.json),
+        (Some(Command::Resolve { uri }), _) => resolve(&uri, &config, args.json),
+        #[cfg(unix)]
+        (Some(Command::Daemon), _) => daemon(config),
+        (Some(Command::Log { command }), _) => log_command(command),
+        (None, uris) => open(&uris, &config, None, args.json),
     }
+}
 
-    // Canonicalize to resolve symlinks and prevent path traversal attacks
-    let canonical_path = path
-        .canonicalize()
-        .map_err(|e| format!("Failed to resolve path {}: {}", path.display(), e))?;
+/// Open URIs received on the command line.
+///
+/// A single URI fails like any other command. With several, a result line is
+/// printed per URI and the exit code is that of the first failure. URIs that
+/// another process has just opened are reported as skipped; if nothing else
+/// was to be done, the process exits quietly with [`debounce::EXIT_COALESCED`].
+fn open(
+    uris: &[String],
+    config: &Config,
+    origin: Option<&str>,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    sandbox::enter(&config.sandbox);
+    let results = open_fresh(uris, config, origin);
+    report(uris, results, json)
+}
 
-    log::info(&format!("Opening: {}", canonical_path.display()));
+/// Open the URIs no other process has just opened; the others are
+/// [`QmpoError::Coalesced`]. URIs that fail to open are released, so retrying
+/// them is not coalesced.
+fn open_fresh(uris: &[String], config: &Config, origin: Option<&str>) -> Vec<error::Result<()>> {
+    let window = Duration::from_millis(config.debounce.window_ms);
+    let claimed: Vec<bool> = uris
+        .iter()
+        .map(|uri| match debounce::claim(uri, window) {
+            Ok(fresh) => {
+                if !fresh {
+                    privacy::track(uri);
+                    log::log(Level::Info, "Coalesced duplicate", &[("uri", uri)]);
+                }
+                fresh
+            }
+            Err(e) => {
+                // Opening twice is better than not opening at all
+                log::log(
+                    Level::Warn,
+                    "Could not check for duplicate launches",
+                    &[("error", &e.to_string())],
+                );
+                true
+            }
+        })
+        .collect();
+    let fresh: Vec<String> = uris
+        .iter()
+        .zip(&claimed)
+        .filter(|(_, claimed)| **claimed)
+        .map(|(uri, _)| uri.clone())
+        .collect();
+    let mut opened = open_all(&fresh, config, origin).into_iter();
 
-    // Open in file manager (with file selected if path is a file)
-    open_in_file_manager(&canonical_path)?;
+    uris.iter()
+        .zip(claimed)
+        .map(|(uri, claimed)| {
+            if !claimed {
+                return Err(QmpoError::Coalesced);
+            }
+            let result = opened.next().unwrap_or(Ok(()));
+            // A failed open must not swallow a deliberate retry
+            if result.is_err()
+                && let Err(e) = debounce::release(uri, window)
+            {
+                log::log(
+                    Level::Warn,
+                    "Could not release duplicate launch check",
+                    &[("error", &e.to_string())],
+                );
+            }
+            result
+        })
+        .collect()
+}
 
+/// Report the results of opening `uris`, one per URI.
+fn report(
+    uris: &[String],
+    mut results: Vec<error::Result<()>>,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    activation::finish(results.iter().any(Result::is_ok));
+    let coalesced =
+        |result: &error::Result<()>| result.as_ref().is_err_and(QmpoError::is_coalesced);
+    if !results.is_empty() && results.iter().all(coalesced) {
+        std::process::exit(debounce::EXIT_COALESCED);
+    }
+    if uris.len() == 1 {
+        return results.remove(0).map_err(|e| Logged(e).into());
+    }
+
+    if json {
+        let items: Vec<_> = uris
+            .iter()
+            .zip(&results)
+            .map(|(uri, result)| match result {
+                Ok(()) => serde_json::json!({ "uri": uri, "ok": true }),
+                Err(e) => serde_json::json!({
+                    "uri": uri,
+                    "ok": false,
+                    "error": e.to_string(),
+                    "kind": e.kind(),
+                    "code": e.exit_code(),
+                }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items)?);
+    } else {
+        for (uri, result) in uris.iter().zip(&results) {
+            match result {
+                Ok(()) => println!("ok      {uri}"),
+                Err(e) if e.is_coalesced() => println!("skipped {uri}  ({e})"),
+                Err(e) => println!("failed  {uri}  ({e})"),
+            }
+        }
+    }
+
+    // URIs another process opened are not failures
+    if let Some(code) = results
+        .iter()
+        .filter(|result| !coalesced(result))
+        .find_map(|r| r.as_ref().err())
+        .map(QmpoError::exit_code)
+    {
+        std::process::exit(code);
+    }
     Ok(())
 }
 
-/// Open a path in the system's file manager.
-/// If the path is a file, opens the parent directory with the file selected.
-#[cfg(target_os = "windows")]
-fn open_in_file_manager(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    if path.is_file() {
-        // Open parent directory with file selected
-        let arg = format!("/select,{}", path.display());
-        Command::new("explorer.exe").arg(&arg).spawn()?;
-    } else {
-        Command::new("explorer.exe").arg(path).spawn()?;
+/// Run the HTTP bridge, sharing the open pipeline with the CLI.
+fn serve(
+    mut config: Config,
+    port: Option<u16>,
+    show_token: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if show_token {
+        println!("{}", serve::load_or_create_token()?);
+        return Ok(());
+    }
+
+    if let Some(port) = port {
+        config.serve.port = port;
     }
+
+    sandbox::enter(&config.sandbox);
+    serve::serve(&config.serve, &|uri, origin| {
+        open_all(&[uri.to_string()], &config, origin).remove(0)
+    })?;
+    Ok(())
+}
+
+/// Run the daemon, sharing the open pipeline with the CLI.
+#[cfg(unix)]
+fn daemon(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    sandbox::enter(&config.sandbox);
+    daemon::run(config, &|config, uris| open_fresh(uris, config, None))?;
+    Ok(())
+}
+
+/// Print a signed link for `path` using the key from the system configuration.
+fn sign(config: &Config, path: &Path, ttl: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let key = config.signing.key.as_deref().ok_or_else(|| {
+        QmpoError::Config("signing.key is not set in the system configuration".to_string())
+    })?;
+    let path = std::path::absolute(path)?;
+    println!("{}", signing::sign(&path, ttl, key, time::now())?);
     Ok(())
 }
 
-/// Open a path in the system's file manager.
-/// If the path is a file, opens the parent directory with the file selected.
-#[cfg(target_os = "macos")]
-fn open_in_file_manager(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    if path.is_file() {
-        // Open parent directory with file selected using -R flag
-        Command::new("open").arg("-R").arg(path).spawn()?;
+/// List the history, or reopen an entry from it.
+fn history(
+    config: &Config,
+    command: Option<HistoryCommand>,
+    since: Option<Duration>,
+    failed: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = history::load()?;
+
+    if let Some(HistoryCommand::Reopen { n }) = command {
+        let entry = history::nth(&entries, n).ok_or(format!("no history entry {n}"))?;
+        let uri = entry.reopen_uri().ok_or(format!(
+            "history entry {n} was redacted and cannot be reopened"
+        ))?;
+        activation::request();
+        return open(&[uri.to_string()], config, Some("history"), json);
+    }
+
+    let filter = history::Filter {
+        since: since.map(|since| time::now().saturating_sub(since.as_secs())),
+        failed,
+    };
+    let selected = history::query(&entries, &filter);
+
+    if json {
+        let values: Vec<_> = selected
+            .iter()
+            .map(|(n, entry)| {
+                let mut value = serde_json::to_value(entry)?;
+                value["n"] = (*n).into();
+                Ok(value)
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+        println!("{}", serde_json::to_string_pretty(&values)?);
     } else {
-        Command::new("open").arg(path).spawn()?;
+        for (n, entry) in selected {
+            println!("{}", history::format_entry(n, entry, config.log.utc));
+        }
     }
     Ok(())
 }
 
-/// Open a path in the system's file manager.
-/// If the path is a file, attempts to open the parent directory with the file selected.
-#[cfg(target_os = "linux")]
-fn open_in_file_manager(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    if path.is_file() {
-        // Try dbus-send to select file in file manager (works with Nautilus, Dolphin, etc.)
-        let file_uri = format!("file://{}", path.display());
-        let dbus_result = Command::new("dbus-send")
-            .args([
-                "--session",
-                "--dest=org.freedesktop.FileManager1",
-                "--type=method_call",
-                "/org/freedesktop/FileManager1",
-                "org.freedesktop.FileManager1.ShowItems",
-                &format!("array:string:{}", file_uri),
-                "string:",
-            ])
-            .status();
-
-        let dbus_succeeded = dbus_result.map(|s| s.success()).unwrap_or(false);
-
-        if !dbus_succeeded {
-            // Fallback: open parent directory without file selection
-            if let Some(parent) = path.parent() {
-                Command::new("xdg-open").arg(parent).spawn()?;
-            }
+/// Print each pipeline stage and the launcher commands for `uri_str`.
+fn resolve(uri_str: &str, config: &Config, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    privacy::track(uri_str);
+    let mut stages = Vec::new();
+    let result = resolve::resolve(uri_str, config, time::now(), &mut stages);
+
+    if json {
+        let argv: Vec<Vec<String>> = result.as_ref().map_or(Vec::new(), |resolution| {
+            resolution
+                .plan
+                .steps
+                .iter()
+                .map(|step| {
+                    step.argv
+                        .iter()
+                        .map(|arg| arg.to_string_lossy().into_owned())
+                        .collect()
+                })
+                .collect()
+        });
+        let mut value = serde_json::json!({ "uri": uri_str, "stages": stages, "launch": argv });
+        if let Err(e) = &result {
+            value["error"] = e.to_string().into();
+            value["kind"] = e.kind().into();
+            value["code"] = e.exit_code().into();
         }
-    } else {
-        Command::new("xdg-open").arg(path).spawn()?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        // The error is part of the output above; only the exit code remains
+        if let Err(e) = result {
+            std::process::exit(e.exit_code());
+        }
+        return Ok(());
+    }
+
+    for stage in &stages {
+        println!("{:<13} {}", format!("{}:", stage.stage), stage.detail);
+    }
+    for step in result?.plan.steps {
+        println!("{:<13} {}", "launch:", step);
     }
     Ok(())
 }
 
-/// Open a directory in the system's file manager.
-#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
-fn open_in_file_manager(_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    Err("Unsupported operating system".into())
+/// Run a `qmpo log` subcommand.
+fn log_command(command: LogCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        LogCommand::Purge { older_than } => {
+            let before = time::now().saturating_sub(older_than.as_secs());
+            let lines = log::purge(before)?;
+            let entries = history::purge(before)?;
+            println!("Removed {lines} log lines and {entries} history entries");
+            Ok(())
+        }
+    }
+}
+
+/// Resolved targets opened with one launch.
+struct Group {
+    /// Shared parent directory of revealed files; `None` for a directory opened on its own.
+    parent: Option<PathBuf>,
+    /// Indices into the URI list, with their resolved paths.
+    items: Vec<(usize, PathBuf)>,
+}
+
+/// Resolve and launch each URI, recording every outcome in the log and history.
+///
+/// Files that share a parent directory are revealed together with one launch.
+/// `origin` is where the URIs came from (the HTTP `Origin`, or `history`);
+/// `None` means the command line.
+fn open_all(uris: &[String], config: &Config, origin: Option<&str>) -> Vec<error::Result<()>> {
+    privacy::clear();
+
+    let mut entries = Vec::new();
+    let mut results: Vec<Option<error::Result<()>>> = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+
+    for (i, uri) in uris.iter().enumerate() {
+        privacy::track(uri);
+        log::log(
+            Level::Info,
+            "Received URI",
+            &[("uri", uri), ("origin", origin.unwrap_or("cli"))],
+        );
+
+        let mut entry = history::Entry::new(uri, origin);
+        match resolve_logged(uri, config) {
+            Ok(resolution) => {
+                entry.path = Some(resolution.path.display().to_string());
+                entry.action = Some(resolution.action);
+                results.push(None);
+
+                // Reveals of files in the same directory share a launch; opens never do
+                let parent = match resolution.action {
+                    history::Action::Reveal => resolution.path.parent().map(Path::to_path_buf),
+                    history::Action::Open => None,
+                };
+                let item = (i, resolution.path);
+                match groups
+                    .iter_mut()
+                    .find(|group| parent.is_some() && group.parent == parent)
+                {
+                    Some(group) => group.items.push(item),
+                    None => groups.push(Group {
+                        parent,
+                        items: vec![item],
+                    }),
+                }
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+        entries.push(entry);
+    }
+
+    for Group { parent, items } in groups {
+        let paths: Vec<&Path> = items.iter().map(|(_, path)| path.as_path()).collect();
+        for path in &paths {
+            log::log(
+                Level::Info,
+                "Opening",
+                &[("path", &path.display().to_string())],
+            );
+        }
+
+        // Open in file manager (with files selected if revealing)
+        let plan = match parent {
+            Some(_) => launcher::plan_reveal(&paths, launcher::backend(config)),
+            None => launcher::plan(paths[0], history::Action::Open, launcher::backend(config)),
+        };
+        // Planning errors keep their kind (e.g. `mapping_failed`); anything
+        // the launch itself raises is a launch failure
+        let result = plan.and_then(|plan| {
+            launcher::launch(&plan).map_err(|e| match e {
+                QmpoError::LaunchFailed(_) => e,
+                other => QmpoError::LaunchFailed(other.to_string()),
+            })
+        });
+        match result {
+            Ok(()) => {
+                for (i, _) in &items {
+                    results[*i] = Some(Ok(()));
+                }
+            }
+            Err(e) => {
+                // Errors cannot be cloned; the other items of the group get a
+                // copy with the same message, kind and exit code
+                for (i, _) in &items[1..] {
+                    results[*i] = Some(Err(QmpoError::forwarded(
+                        e.to_string(),
+                        e.kind(),
+                        e.exit_code(),
+                    )));
+                }
+                results[items[0].0] = Some(Err(e));
+            }
+        }
+    }
+
+    entries
+        .iter_mut()
+        .zip(results)
+        .map(|(entry, result)| {
+            let result = result.unwrap_or(Ok(()));
+            entry.finish(&result);
+            history::append(entry);
+            match &result {
+                Ok(()) => log::log(Level::Info, "Completed successfully", &[("result", "ok")]),
+                Err(e) => log::log(
+                    Level::Error,
+                    "Failed",
+                    &[
+                        ("uri", &entry.uri),
+                        ("result", "failed"),
+                        ("error", &e.to_string()),
+                    ],
+                ),
+            }
+            result
+        })
+        .collect()
+}
+
+/// Run the pipeline for one URI, logging each stage.
+fn resolve_logged(uri_str: &str, config: &Config) -> error::Result<resolve::Resolution> {
+    let mut stages = Vec::new();
+    let result = resolve::resolve(uri_str, config, time::now(), &mut stages);
+    for stage in &stages {
+        log::log(
+            Level::Debug,
+            "Resolved",
+            &[("stage", stage.stage), ("detail", &stage.detail)],
+        );
+    }
+    result
 }
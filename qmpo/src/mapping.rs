@@ -0,0 +1,227 @@
+//! Prefix mappings from link paths to local paths.
+//!
+//! Intranet links usually name Windows shares (`\\fileserver\share\...`).
+//! A `[[mappings]]` entry rewrites such a prefix to where the share is
+//! mounted on this machine, e.g. `/mnt/share` on Linux.
+//!
+//! WebDAV folders may be given either as UNC paths or as URLs, and mapped to
+//! where gvfs mounts them (see [`crate::webdav`]).
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::webdav::{self, WebDav};
+use crate::winpath;
+
+/// A single `[[mappings]]` entry.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// Path prefix as it appears in links (e.g. `\\fileserver\share` or `C:\Shared`),
+    /// or the `https://` URL of a WebDAV folder.
+    pub from: String,
+    /// Local path that replaces the prefix, or the `davs://` (or `dav://`)
+    /// location of a gvfs mount.
+    pub to: String,
+}
+
+impl Mapping {
+    /// Rewrite `path` if it starts with this mapping's prefix.
+    ///
+    /// Prefixes match whole components. `/` and `\` are interchangeable, and
+    /// Windows prefixes (drive letters and UNC shares) match case-insensitively.
+    /// Extended-length forms such as `\\?\UNC\server\share` match their plain
+    /// spelling, and a WebDAV folder matches in any of its spellings.
+    pub fn apply(&self, path: &str) -> Option<PathBuf> {
+        self.apply_in(path, webdav::gvfs_root().as_deref())
+    }
+
+    fn apply_in(&self, path: &str, gvfs_root: Option<&Path>) -> Option<PathBuf> {
+        let path = winpath::simplify(path);
+        let rest = match WebDav::parse(&self.from) {
+            Some(from) => WebDav::parse(&path)?.strip_prefix(&from)?.to_vec(),
+            None => self.strip_prefix(&path)?,
+        };
+
+        let mut mapped = match self.to.split_once("://") {
+            Some((scheme, _))
+                if scheme.eq_ignore_ascii_case("dav") || scheme.eq_ignore_ascii_case("davs") =>
+            {
+                WebDav::from_url(&self.to)?.gvfs_dir(gvfs_root?)
+            }
+            _ => PathBuf::from(&self.to),
+        };
+        for component in rest {
+            mapped.push(component);
+        }
+        Some(mapped)
+    }
+
+    /// The components of `path` after the `from` prefix.
+    fn strip_prefix(&self, path: &str) -> Option<Vec<String>> {
+        let prefix = winpath::simplify(&self.from);
+        let from = split(&prefix);
+        let components = split(path);
+        if from.is_empty() || components.len() < from.len() {
+            return None;
+        }
+
+        let windows = is_windows_path(&prefix);
+        let matches = from.iter().zip(&components).all(|(a, b)| {
+            if windows {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        });
+        if !matches || is_unc(&prefix) != is_unc(path) {
+            return None;
+        }
+        Some(
+            components[from.len()..]
+                .iter()
+                .map(|component| component.to_string())
+                .collect(),
+        )
+    }
+}
+
+/// Apply the first mapping whose prefix matches `path`.
+pub fn apply<'a>(mappings: &'a [Mapping], path: &Path) -> Option<(PathBuf, &'a Mapping)> {
+    let path = path.to_str()?;
+    mappings
+        .iter()
+        .find_map(|mapping| mapping.apply(path).map(|mapped| (mapped, mapping)))
+}
+
+/// Split a path into its non-empty components on either separator.
+fn split(path: &str) -> Vec<&str> {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .collect()
+}
+
+fn is_unc(path: &str) -> bool {
+    path.starts_with("\\\\") || path.starts_with("//")
+}
+
+fn is_windows_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    is_unc(path) || (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(from: &str, to: &str) -> Mapping {
+        Mapping {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unc_prefix() {
+        let m = mapping(r"\\fileserver\share", "/mnt/share");
+        assert_eq!(
+            m.apply(r"\\FileServer\Share\HR\2026"),
+            Some(PathBuf::from("/mnt/share/HR/2026"))
+        );
+        assert_eq!(
+            m.apply(r"\\fileserver\share"),
+            Some(PathBuf::from("/mnt/share"))
+        );
+        // Whole components only
+        assert_eq!(m.apply(r"\\fileserver\shared\x"), None);
+        assert_eq!(m.apply(r"\\other\share\x"), None);
+    }
+
+    #[test]
+    fn test_forward_slash_prefix() {
+        let m = mapping("//fileserver/share", "/mnt/share");
+        assert_eq!(
+            m.apply(r"\\fileserver\share\a"),
+            Some(PathBuf::from("/mnt/share/a"))
+        );
+    }
+
+    #[test]
+    fn test_drive_prefix() {
+        let m = mapping(r"S:\", "/mnt/s");
+        assert_eq!(
+            m.apply(r"s:\Projects"),
+            Some(PathBuf::from("/mnt/s/Projects"))
+        );
+        assert_eq!(m.apply(r"\\s\Projects"), None);
+    }
+
+    #[test]
+    fn test_extended_length_forms() {
+        let m = mapping(r"\\fileserver\share", "/mnt/share");
+        assert_eq!(
+            m.apply(r"\\?\UNC\fileserver\share\HR"),
+            Some(PathBuf::from("/mnt/share/HR"))
+        );
+        let m = mapping(r"\\?\S:\", "/mnt/s");
+        assert_eq!(
+            m.apply(r"S:\Projects"),
+            Some(PathBuf::from("/mnt/s/Projects"))
+        );
+        // A device is not the drive it may hold
+        assert_eq!(m.apply(r"\\.\PhysicalDrive0"), None);
+    }
+
+    #[test]
+    fn test_webdav_prefix() {
+        let root = Path::new("/run/user/1000/gvfs");
+        let m = mapping(
+            "https://files.example.com/sites/team",
+            "davs://files.example.com/sites/team",
+        );
+        assert_eq!(
+            m.apply_in(
+                r"\\files.example.com@SSL@443\DavWWWRoot\sites\Team\Q1 report",
+                Some(root)
+            ),
+            Some(root.join("dav:host=files.example.com,ssl=true,prefix=%2Fsites%2Fteam/Q1 report"))
+        );
+        // Plain HTTP is another server
+        assert_eq!(
+            m.apply_in(r"\\files.example.com\DavWWWRoot\sites\team", Some(root)),
+            None
+        );
+        // Without a runtime directory there is no gvfs mount to map to
+        assert_eq!(
+            m.apply_in(r"\\files.example.com@SSL\sites\team", None),
+            None
+        );
+
+        // A UNC prefix also matches the WebDAV folder, and may map to a path
+        let m = mapping(r"\\files.example.com@SSL\DavWWWRoot", "/mnt/dav");
+        assert_eq!(
+            m.apply(r"\\files.example.com@SSL@443\DavWWWRoot\sites"),
+            Some(PathBuf::from("/mnt/dav/sites"))
+        );
+    }
+
+    #[test]
+    fn test_unix_prefix_is_case_sensitive() {
+        let m = mapping("/srv/share", "/mnt/share");
+        assert_eq!(m.apply("/srv/share/a"), Some(PathBuf::from("/mnt/share/a")));
+        assert_eq!(m.apply("/srv/Share/a"), None);
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let mappings = vec![
+            mapping(r"\\fs\share\hr", "/mnt/hr"),
+            mapping(r"\\fs\share", "/mnt/share"),
+        ];
+        let (mapped, used) = apply(&mappings, Path::new(r"\\fs\share\hr\a")).unwrap();
+        assert_eq!(mapped, PathBuf::from("/mnt/hr/a"));
+        assert_eq!(used.to, "/mnt/hr");
+        assert!(apply(&mappings, Path::new("/home/user")).is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Per-user placeholders in link paths.
+//!
+//! Shared documents cannot name "everyone's Downloads" with an absolute path.
+//! Placeholders stand for a location that differs per user and are expanded
+//! on the machine that opens the link:
+//!
+//! - `directory:///~/...`: the home directory
+//! - `directory://known/<Name>/...`: the `Desktop`, `Documents` or `Downloads`
+//!   folder, read from `user-dirs.dirs` on Linux and from the known folders on
+//!   Windows and macOS
+//! - `directory://env/<NAME>/...`: an environment variable, if `[placeholders]`
+//!   allows it
+//!
+//! Links are signed in their placeholder form, so one signed link serves
+//! every user.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use directories::{BaseDirs, UserDirs};
+use serde::Deserialize;
+
+use crate::error::{QmpoError, Result};
+use crate::winpath::{Namespace, Root, WinPath};
+
+/// Settings for the `[placeholders]` config section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaceholderConfig {
+    /// Environment variables links may name as `directory://env/NAME/...`.
+    pub env: Vec<String>,
+}
+
+/// A folder every user has, under a name that is the same for all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownFolder {
+    Desktop,
+    Documents,
+    Downloads,
+}
+
+impl KnownFolder {
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Desktop, Self::Documents, Self::Downloads]
+            .into_iter()
+            .find(|folder| folder.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Desktop => "Desktop",
+            Self::Documents => "Documents",
+            Self::Downloads => "Downloads",
+        }
+    }
+}
+
+/// The location a placeholder stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    /// `~`: the home directory.
+    Home,
+    /// `known/<Name>`: a known folder.
+    Known(KnownFolder),
+    /// `env/<NAME>`: the value of an environment variable.
+    Env(String),
+}
+
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Home => f.write_str("~"),
+            Self::Known(folder) => write!(f, "known/{}", folder.name()),
+            Self::Env(name) => write!(f, "env/{name}"),
+        }
+    }
+}
+
+/// A path below a placeholder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    pub base: Base,
+    /// The components below the base.
+    pub rest: Vec<String>,
+}
+
+impl Placeholder {
+    /// Recognize a placeholder in a parsed path: `/~/...`, `\\known\<Name>\...`
+    /// or `\\env\<NAME>\...`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`QmpoError::InvalidUri`] for an unknown folder name, a missing
+    /// name, or a component that could leave the placeholder's location: `..`,
+    /// or one that could read as a drive or root, such as `C:`.
+    pub fn from_path(path: &Path) -> Result<Option<Self>> {
+        let Some(path) = path.to_str() else {
+            return Ok(None);
+        };
+        let (base, rest) = if let Some(rest) = path.strip_prefix("/~") {
+            if !(rest.is_empty() || rest.starts_with('/')) {
+                return Ok(None);
+            }
+            (Base::Home, rest)
+        } else {
+            let (namespace, name, rest) = match WinPath::parse(path) {
+                Some(WinPath {
+                    namespace: Namespace::Win32,
+                    root: Root::Unc { server, share },
+                    rest,
+                }) => (server, share, rest),
+                // A namespace without a name is a lone server
+                _ => match path.strip_prefix(r"\\") {
+                    Some(server) if is_namespace(server.trim_end_matches('\\')) => {
+                        (server.trim_end_matches('\\'), "", "")
+                    }
+                    _ => return Ok(None),
+                },
+            };
+            if !is_namespace(namespace) {
+                return Ok(None);
+            }
+            if name.is_empty() {
+                return Err(QmpoError::InvalidUri(format!(
+                    "missing name after {namespace}/"
+                )));
+            }
+            let base = if namespace.eq_ignore_ascii_case("known") {
+                Base::Known(KnownFolder::from_name(name).ok_or_else(|| {
+                    QmpoError::InvalidUri(format!("unknown known folder: {name}"))
+                })?)
+            } else {
+                Base::Env(name.to_string())
+            };
+            (base, rest)
+        };
+
+        let rest: Vec<String> = rest
+            .split(['/', '\\'])
+            .filter(|component| !component.is_empty() && *component != ".")
+            .map(str::to_string)
+            .collect();
+        if rest.iter().any(|component| component == "..") {
+            return Err(QmpoError::InvalidUri(format!(
+                "'..' may not follow a placeholder: {path}"
+            )));
+        }
+        // `PathBuf::push` replaces the base with a component that has a root
+        // or a prefix, such as `C:` on Windows; `:` is refused everywhere so a
+        // signed link means the same on every platform
+        if let Some(component) = rest.iter().find(|component| !is_normal(component)) {
+            return Err(QmpoError::InvalidUri(format!(
+                "'{component}' may not follow a placeholder: {path}"
+            )));
+        }
+        Ok(Some(Self { base, rest }))
+    }
+
+    /// The path this placeholder names, with `locate` finding the base.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`QmpoError::PolicyDenied`] for an environment variable that is
+    /// not allowed, or [`QmpoError::MappingFailed`] if the base has no location
+    /// on this machine.
+    pub fn expand(
+        &self,
+        config: &PlaceholderConfig,
+        locate: impl FnOnce(&Base) -> Option<PathBuf>,
+    ) -> Result<PathBuf> {
+        if let Base::Env(name) = &self.base
+            && !config.env.iter().any(|allowed| allowed == name)
+        {
+            return Err(QmpoError::PolicyDenied(format!(
+                "environment variable {name} is not allowed in links"
+            )));
+        }
+        let mut path =
+            locate(&self.base).ok_or_else(|| QmpoError::MappingFailed(self.to_string().into()))?;
+        path.extend(&self.rest);
+        Ok(path)
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base)?;
+        for component in &self.rest {
+            write!(f, "/{component}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `component` is a single plain path component.
+fn is_normal(component: &str) -> bool {
+    let mut components = Path::new(component).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) && !component.contains(':')
+}
+
+fn is_namespace(server: &str) -> bool {
+    server.eq_ignore_ascii_case("known") || server.eq_ignore_ascii_case("env")
+}
+
+/// Where `base` is for the current user.
+pub fn locate(base: &Base) -> Option<PathBuf> {
+    match base {
+        Base::Home => BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf()),
+        Base::Known(folder) => {
+            let dirs = UserDirs::new()?;
+            match folder {
+                KnownFolder::Desktop => dirs.desktop_dir(),
+                KnownFolder::Documents => dirs.document_dir(),
+                KnownFolder::Downloads => dirs.download_dir(),
+            }
+            .map(Path::to_path_buf)
+        }
+        Base::Env(name) => std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholder(path: &str) -> Option<Placeholder> {
+        Placeholder::from_path(Path::new(path)).unwrap()
+    }
+
+    fn locate_test(base: &Base) -> Option<PathBuf> {
+        match base {
+            Base::Home => Some(PathBuf::from("/home/me")),
+            Base::Known(KnownFolder::Downloads) => Some(PathBuf::from("/home/me/Downloads")),
+            Base::Known(_) => None,
+            Base::Env(name) => (name == "PROJECTS").then(|| PathBuf::from("/srv/projects")),
+        }
+    }
+
+    #[test]
+    fn test_recognized_forms() {
+        let cases = [
+            ("/~", Base::Home, vec![]),
+            ("/~/notes/a b", Base::Home, vec!["notes", "a b"]),
+            (
+                r"\\known\downloads\x",
+                Base::Known(KnownFolder::Downloads),
+                vec!["x"],
+            ),
+            (
+                r"\\KNOWN\Desktop",
+                Base::Known(KnownFolder::Desktop),
+                vec![],
+            ),
+            (
+                r"\\env\PROJECTS\qmpo\.\src",
+                Base::Env("PROJECTS".to_string()),
+                vec!["qmpo", "src"],
+            ),
+        ];
+        for (path, base, rest) in cases {
+            assert_eq!(
+                placeholder(path),
+                Some(Placeholder {
+                    base,
+                    rest: rest.into_iter().map(str::to_string).collect()
+                }),
+                "{path}"
+            );
+        }
+
+        for path in [
+            "/home/me",
+            "/~me/x",
+            r"\\server\share",
+            r"C:\~",
+            r"\\?\UNC\known\Desktop",
+        ] {
+            assert_eq!(placeholder(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_forms() {
+        for (path, message) in [
+            (r"\\known\Music", "unknown known folder: Music"),
+            (r"\\known", "missing name after known/"),
+            (r"\\known\", "missing name after known/"),
+            (r"\\env", "missing name after env/"),
+            ("/~/../../etc", "'..' may not follow a placeholder"),
+            (r"\\known\Desktop\..\x", "'..' may not follow a placeholder"),
+            (
+                r"\\known\Downloads\C:\x",
+                "'C:' may not follow a placeholder",
+            ),
+            ("/~/D:", "'D:' may not follow a placeholder"),
+        ] {
+            let err = Placeholder::from_path(Path::new(path)).unwrap_err();
+            assert!(err.to_string().contains(message), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let config = PlaceholderConfig {
+            env: vec!["PROJECTS".to_string()],
+        };
+        let expand = |path| placeholder(path).unwrap().expand(&config, locate_test);
+
+        assert_eq!(expand("/~/notes").unwrap(), PathBuf::from("/home/me/notes"));
+        assert_eq!(
+            expand(r"\\known\Downloads\a.zip").unwrap(),
+            PathBuf::from("/home/me/Downloads/a.zip")
+        );
+        assert_eq!(
+            expand(r"\\env\PROJECTS\qmpo").unwrap(),
+            PathBuf::from("/srv/projects/qmpo")
+        );
+
+        // Not on the allowlist, even though it is set
+        let err = expand(r"\\env\HOME\x").unwrap_err();
+        assert!(matches!(err, QmpoError::PolicyDenied(_)));
+        // Environment variable names are case-sensitive
+        assert!(expand(r"\\env\projects").is_err());
+
+        let err = expand(r"\\known\Desktop\x").unwrap_err();
+        assert_eq!(err.to_string(), "no mapping for path: known/Desktop/x");
+    }
+}
@@ -0,0 +1,260 @@
+//! Opening paths through the XDG desktop portal.
+//!
+//! Inside Flatpak or Snap, neither FileManager1 nor `xdg-open` reaches the
+//! host's file manager. The `org.freedesktop.portal.OpenURI` interface does:
+//! qmpo hands it an `O_PATH` file descriptor for the target and the portal,
+//! running outside the sandbox, opens it. `OpenDirectory` shows the directory
+//! containing a file with the file selected; `OpenFile` opens a directory
+//! itself.
+//!
+//! The portal replies as soon as it has accepted the request; whether the user
+//! picked an application is reported later and not waited for.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use zbus::blocking::{Connection, Proxy, connection};
+use zbus::zvariant::{Fd, OwnedObjectPath, Value};
+
+use crate::error::{QmpoError, Result};
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+const INTERFACE: &str = "org.freedesktop.portal.OpenURI";
+
+/// How long the portal may take to accept a request.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An `OpenURI` method taking a file descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Open a directory in the file manager.
+    OpenFile,
+    /// Show the directory containing a file, with the file selected.
+    OpenDirectory,
+}
+
+impl Method {
+    fn name(self) -> &'static str {
+        match self {
+            Self::OpenFile => "OpenFile",
+            Self::OpenDirectory => "OpenDirectory",
+        }
+    }
+
+    /// The fully qualified method name, for display.
+    pub fn qualified_name(self) -> String {
+        format!("{INTERFACE}.{}", self.name())
+    }
+}
+
+/// Whether qmpo, or the browser that started it, runs in a Flatpak or Snap sandbox.
+pub fn sandboxed() -> bool {
+    detect(
+        Path::new("/.flatpak-info"),
+        std::env::var_os("SNAP").as_deref(),
+    )
+}
+
+fn detect(flatpak_info: &Path, snap: Option<&std::ffi::OsStr>) -> bool {
+    flatpak_info.exists() || snap.is_some_and(|snap| !snap.is_empty())
+}
+
+/// Call `method` for `path` on the session bus's portal.
+pub fn open(method: Method, path: &Path, token: Option<&str>) -> Result<()> {
+    let connection = connection::Builder::session()
+        .map(|builder| builder.method_timeout(TIMEOUT))
+        .and_then(|builder| builder.build())
+        .map_err(portal_error)?;
+    open_on(&connection, method, path, token)
+}
+
+/// Call `method` for `path` on the portal reachable through `connection`.
+fn open_on(
+    connection: &Connection,
+    method: Method,
+    path: &Path,
+    token: Option<&str>,
+) -> Result<()> {
+    // O_PATH grants no access to the contents; the portal only resolves it
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(path)
+        .map_err(|e| QmpoError::LaunchFailed(format!("{}: {e}", path.display())))?;
+
+    let mut options: HashMap<&str, Value<'_>> = HashMap::new();
+    if let Some(token) = token {
+        options.insert("activation_token", token.into());
+    }
+    let proxy =
+        Proxy::new(connection, DESTINATION, OBJECT_PATH, INTERFACE).map_err(portal_error)?;
+    // No parent window: qmpo has none to offer
+    let _request: OwnedObjectPath = proxy
+        .call(method.name(), &("", Fd::from(&file), options))
+        .map_err(portal_error)?;
+    Ok(())
+}
+
+fn portal_error(e: zbus::Error) -> QmpoError {
+    QmpoError::LaunchFailed(format!("desktop portal: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::os::fd::AsRawFd;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+
+    use zbus::zvariant::{OwnedFd, OwnedValue};
+
+    #[test]
+    fn test_detect() {
+        let missing = Path::new("/nonexistent/.flatpak-info");
+        assert!(!detect(missing, None));
+        assert!(!detect(missing, Some("".as_ref())));
+        assert!(detect(missing, Some("/snap/firefox/1234".as_ref())));
+        // Any existing file stands in for /.flatpak-info
+        assert!(detect(Path::new("/"), None));
+    }
+
+    /// Calls received by the stub portal: method, target path and token.
+    type Calls = Arc<Mutex<Vec<(String, String, Option<String>)>>>;
+
+    struct StubPortal {
+        calls: Calls,
+    }
+
+    impl StubPortal {
+        fn record(&self, method: &str, fd: OwnedFd, options: HashMap<String, OwnedValue>) {
+            let target = fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd())).unwrap();
+            let token = options
+                .get("activation_token")
+                .and_then(|value| String::try_from(value.clone()).ok());
+            self.calls.lock().unwrap().push((
+                method.to_string(),
+                target.display().to_string(),
+                token,
+            ));
+        }
+    }
+
+    #[zbus::interface(name = "org.freedesktop.portal.OpenURI")]
+    impl StubPortal {
+        fn open_file(
+            &self,
+            _parent_window: &str,
+            fd: OwnedFd,
+            options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            self.record("OpenFile", fd, options);
+            OwnedObjectPath::try_from("/org/freedesktop/portal/desktop/request/1").unwrap()
+        }
+
+        fn open_directory(
+            &self,
+            _parent_window: &str,
+            fd: OwnedFd,
+            options: HashMap<String, OwnedValue>,
+        ) -> OwnedObjectPath {
+            self.record("OpenDirectory", fd, options);
+            OwnedObjectPath::try_from("/org/freedesktop/portal/desktop/request/2").unwrap()
+        }
+    }
+
+    /// A session bus of its own, stopped when dropped.
+    struct PrivateBus(Child);
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Start `dbus-daemon` on a private socket, returning it and its address.
+    fn private_bus() -> Option<(PrivateBus, String)> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(child.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some((PrivateBus(child), address.trim().to_string()))
+    }
+
+    #[test]
+    fn test_open_through_stub_portal() {
+        let Some((_bus, address)) = private_bus() else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let calls = Calls::default();
+        let _portal = connection::Builder::address(address.as_str())
+            .unwrap()
+            .name(DESTINATION)
+            .unwrap()
+            .serve_at(
+                OBJECT_PATH,
+                StubPortal {
+                    calls: calls.clone(),
+                },
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("qmpo_test_portal_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "a").unwrap();
+
+        let client = connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        open_on(&client, Method::OpenDirectory, &file, Some("token-123")).unwrap();
+        open_on(&client, Method::OpenFile, &dir, None).unwrap();
+        let err = open_on(&client, Method::OpenFile, &dir.join("missing"), None).unwrap_err();
+        assert!(matches!(err, QmpoError::LaunchFailed(_)));
+
+        let calls = calls.lock().unwrap().clone();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(
+            calls,
+            [
+                (
+                    "OpenDirectory".to_string(),
+                    file.display().to_string(),
+                    Some("token-123".to_string())
+                ),
+                ("OpenFile".to_string(), dir.display().to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_without_portal() {
+        let Some((_bus, address)) = private_bus() else {
+            eprintln!("dbus-daemon not available; skipping");
+            return;
+        };
+        let client = connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        let err = open_on(&client, Method::OpenFile, Path::new("/"), None).unwrap_err();
+        assert!(err.to_string().contains("desktop portal"));
+    }
+}
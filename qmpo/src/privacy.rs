@@ -0,0 +1,338 @@
+//! Redaction of opened paths in logs and history.
+//!
+//! The URIs being handled and the paths resolved from them are registered with
+//! [`track`] and [`track_path`]. Every string written to the log or the history
+//! then passes through [`scrub`], which replaces those paths according to the
+//! `[privacy]` config section: kept as is, cut to their first components, or
+//! replaced with a keyed hash. Hashes use a per-install key (`privacy.key` in
+//! the data directory), so the same path always yields the same hash on one
+//! machine without being guessable elsewhere.
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::secret;
+use crate::uri::DirectoryUri;
+use crate::winpath::WinPath;
+
+/// Number of hex digits kept from a path hash.
+const HASH_LEN: usize = 16;
+
+/// How opened paths are written to logs and history.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// Record full paths.
+    #[default]
+    Keep,
+    /// Keep the first `components` path components (`/srv/share/…`).
+    Truncate,
+    /// Replace paths with a keyed hash (`hash:1a2b3c4d5e6f7a8b`).
+    Hash,
+}
+
+/// Settings for the `[privacy]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// Redaction applied to paths and URIs.
+    pub redact: Redaction,
+    /// Components kept by `truncate`.
+    pub components: usize,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            redact: Redaction::Keep,
+            components: 2,
+        }
+    }
+}
+
+static REDACTOR: RwLock<Option<Arc<Redactor>>> = RwLock::new(None);
+
+/// Sensitive strings seen during the current invocation, with their replacements.
+static TRACKED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Install the redaction settings, loading the hash key if needed.
+///
+/// Until this is called (or if the config failed to load), nothing is redacted.
+/// Calling it again replaces the settings (e.g. when `qmpo daemon` reloads).
+pub fn init(config: &PrivacyConfig) -> io::Result<()> {
+    let redactor = Arc::new(Redactor::new(config)?);
+    *REDACTOR.write().unwrap_or_else(|e| e.into_inner()) = Some(redactor);
+    Ok(())
+}
+
+fn redactor() -> Option<Arc<Redactor>> {
+    REDACTOR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .filter(|redactor| redactor.mode != Redaction::Keep)
+}
+
+/// The redaction currently applied.
+pub fn mode() -> Redaction {
+    redactor().map_or(Redaction::Keep, |redactor| redactor.mode)
+}
+
+/// Forget the URIs and paths of earlier requests.
+pub fn clear() {
+    TRACKED.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Register a received URI and the path it names.
+pub fn track(uri: &str) {
+    let Some(redactor) = redactor() else {
+        return;
+    };
+    let mut tracked = TRACKED.lock().unwrap_or_else(|e| e.into_inner());
+    tracked.push((uri.to_string(), redactor.uri(uri)));
+    if let Ok(parsed) = DirectoryUri::parse(uri) {
+        let path = parsed.path().display().to_string();
+        tracked.push((path.clone(), redactor.path(&path)));
+    }
+}
+
+/// Register a path derived from a tracked URI (e.g. its mapped or canonical form).
+pub fn track_path(path: &Path) {
+    let Some(redactor) = redactor() else {
+        return;
+    };
+    let path = path.display().to_string();
+    let redacted = redactor.path(&path);
+    TRACKED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((path, redacted));
+}
+
+/// Replace every tracked URI and path in `text`.
+pub fn scrub(text: &str) -> String {
+    if redactor().is_none() {
+        return text.to_string();
+    }
+    let tracked = TRACKED.lock().unwrap_or_else(|e| e.into_inner());
+    replace_all(text, &tracked)
+}
+
+/// Replace each `(from, to)` pair, longest `from` first so a URI is replaced
+/// before the path it contains.
+fn replace_all(text: &str, pairs: &[(String, String)]) -> String {
+    let mut pairs: Vec<_> = pairs.iter().filter(|(from, _)| !from.is_empty()).collect();
+    pairs.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+
+    let mut text = text.to_string();
+    for (from, to) in pairs {
+        text = text.replace(from.as_str(), to);
+    }
+    text
+}
+
+/// Applies one redaction mode to paths and URIs.
+#[derive(Debug)]
+pub struct Redactor {
+    mode: Redaction,
+    components: usize,
+    key: Vec<u8>,
+}
+
+impl Redactor {
+    /// Create a redactor; `hash` mode reads or creates the per-install key.
+    pub fn new(config: &PrivacyConfig) -> io::Result<Self> {
+        let key = match config.redact {
+            Redaction::Hash => secret::load_or_create("privacy.key")?.into_bytes(),
+            Redaction::Keep | Redaction::Truncate => Vec::new(),
+        };
+        Ok(Self::with_key(config, key))
+    }
+
+    fn with_key(config: &PrivacyConfig, key: Vec<u8>) -> Self {
+        Self {
+            mode: config.redact,
+            components: config.components,
+            key,
+        }
+    }
+
+    /// Redact a filesystem path.
+    pub fn path(&self, path: &str) -> String {
+        match self.mode {
+            Redaction::Keep => path.to_string(),
+            Redaction::Truncate => truncate(path, self.components),
+            Redaction::Hash => self.hash(path),
+        }
+    }
+
+    /// Redact a `directory://` URI by redacting the path it names.
+    ///
+    /// Unparseable URIs are hidden entirely unless the mode is `keep`.
+    pub fn uri(&self, uri: &str) -> String {
+        if self.mode == Redaction::Keep {
+            return uri.to_string();
+        }
+        match DirectoryUri::parse(uri) {
+            Ok(parsed) => {
+                let path = self.path(&parsed.path().display().to_string());
+                format!("directory:///{}", path.trim_start_matches('/'))
+            }
+            Err(_) => "directory://…".to_string(),
+        }
+    }
+
+    fn hash(&self, path: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(path.as_bytes());
+        let digest: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("hash:{}", &digest[..HASH_LEN])
+    }
+}
+
+/// Keep the root and the first `n` components of `path`, marking the cut with `…`.
+///
+/// Drive and UNC paths are split on `\` on every platform; elsewhere they
+/// would be a single component and kept whole.
+fn truncate(path: &str, n: usize) -> String {
+    if let Some(parsed) = WinPath::parse(path) {
+        let mut kept = WinPath { rest: "", ..parsed }.to_string();
+        let components: Vec<&str> = parsed.rest.split('\\').filter(|c| !c.is_empty()).collect();
+        if components.is_empty() {
+            return path.to_string();
+        }
+        for component in components.iter().take(n) {
+            kept.push('\\');
+            kept.push_str(component);
+        }
+        if components.len() > n {
+            kept.push_str("\\…");
+        }
+        return kept;
+    }
+
+    let mut kept = PathBuf::new();
+    let mut count = 0;
+    for component in Path::new(path).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => kept.push(component),
+            _ if count == n => {
+                kept.push("…");
+                break;
+            }
+            _ => {
+                kept.push(component);
+                count += 1;
+            }
+        }
+    }
+    kept.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(redact: Redaction, components: usize) -> Redactor {
+        Redactor::with_key(&PrivacyConfig { redact, components }, b"test key".to_vec())
+    }
+
+    #[test]
+    fn test_keep() {
+        let redactor = redactor(Redaction::Keep, 2);
+        assert_eq!(redactor.path("/srv/hr/alice"), "/srv/hr/alice");
+        assert_eq!(
+            redactor.uri("directory:///srv/hr/alice"),
+            "directory:///srv/hr/alice"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let redactor = redactor(Redaction::Truncate, 2);
+        assert_eq!(redactor.path("/srv/hr/medical/alice"), "/srv/hr/…");
+        assert_eq!(redactor.path("/srv/hr"), "/srv/hr");
+        assert_eq!(
+            redactor.uri("directory:///srv/hr/medical%20records"),
+            "directory:///srv/hr/…"
+        );
+        assert_eq!(truncate("/srv/hr/alice", 0), "/…");
+
+        // Windows forms, as parsed from `directory://server/share/...` on any platform
+        assert_eq!(
+            redactor.path(r"\\fileserver\hr\medical\alice\x"),
+            r"\\fileserver\hr\medical\alice\…"
+        );
+        assert_eq!(redactor.path(r"C:\HR\medical\alice"), r"C:\HR\medical\…");
+        assert_eq!(redactor.path(r"C:\HR"), r"C:\HR");
+        assert_eq!(redactor.path(r"C:\"), r"C:\");
+        assert_eq!(
+            truncate(r"\\fileserver\hr\medical", 0),
+            r"\\fileserver\hr\…"
+        );
+        assert_eq!(
+            redactor.uri("directory://fileserver/hr/medical/alice/x"),
+            r"directory:///\\fileserver\hr\medical\alice\…"
+        );
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_keyed() {
+        let redactor = redactor(Redaction::Hash, 2);
+        let hash = redactor.path("/srv/hr/alice");
+        assert!(hash.starts_with("hash:"));
+        assert_eq!(hash.len(), "hash:".len() + HASH_LEN);
+        assert_eq!(redactor.path("/srv/hr/alice"), hash);
+        assert_ne!(redactor.path("/srv/hr/bob"), hash);
+
+        let other = Redactor::with_key(
+            &PrivacyConfig {
+                redact: Redaction::Hash,
+                components: 2,
+            },
+            b"another key".to_vec(),
+        );
+        assert_ne!(other.path("/srv/hr/alice"), hash);
+
+        // A URI hashes to the same value as its path
+        assert_eq!(
+            redactor.uri("directory:///srv/hr/alice"),
+            format!("directory:///{hash}")
+        );
+    }
+
+    #[test]
+    fn test_unparseable_uri_hidden() {
+        let redactor = redactor(Redaction::Truncate, 2);
+        assert_eq!(redactor.uri("http://example.com/secret"), "directory://…");
+    }
+
+    #[test]
+    fn test_replace_all_prefers_longest() {
+        let pairs = vec![
+            ("/srv/hr/alice".to_string(), "/srv/hr/…".to_string()),
+            (
+                "directory:///srv/hr/alice".to_string(),
+                "directory:///srv/hr/…".to_string(),
+            ),
+        ];
+        assert_eq!(
+            replace_all(
+                "uri=directory:///srv/hr/alice error=\"Path does not exist: /srv/hr/alice\"",
+                &pairs
+            ),
+            "uri=directory:///srv/hr/… error=\"Path does not exist: /srv/hr/…\""
+        );
+    }
+}
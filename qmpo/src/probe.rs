@@ -0,0 +1,248 @@
+//! Filesystem probes with a deadline.
+//!
+//! `canonicalize()` and `is_file()` can block for tens of seconds when the
+//! SMB or NFS server behind a path is unreachable. [`probe`] runs them on a
+//! worker thread and gives up after `[probe] timeout_ms`, naming the server
+//! that did not answer. The worker is left behind; it finishes (or dies with
+//! the process) once the kernel gives up on the server.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{QmpoError, Result};
+
+/// What to do when a probe times out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTimeout {
+    /// Fail with "server unreachable".
+    #[default]
+    Abort,
+    /// Hand the path to the file manager as given, without canonicalizing it.
+    Passthrough,
+}
+
+/// Settings for the `[probe]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// Milliseconds to wait for the filesystem before giving up.
+    pub timeout_ms: u64,
+    /// What to do when the filesystem does not answer in time.
+    pub on_timeout: OnTimeout,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 3000,
+            on_timeout: OnTimeout::Abort,
+        }
+    }
+}
+
+impl ProbeConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// What a probe found out about a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probed {
+    /// The canonical path.
+    pub path: PathBuf,
+    pub is_file: bool,
+}
+
+/// Canonicalize `path` and check whether it is a file, waiting at most `timeout`.
+pub fn probe(path: &Path, timeout: Duration) -> Result<Probed> {
+    probe_with(path, timeout, |path| {
+        let canonical = path.canonicalize()?;
+        let is_file = fs::metadata(&canonical)?.is_file();
+        Ok(Probed {
+            path: canonical,
+            is_file,
+        })
+    })
+}
+
+/// Run `probe` for `path` on a worker thread, waiting at most `timeout`.
+pub fn probe_with<T, F>(path: &Path, timeout: Duration, probe: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Path) -> io::Result<T> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let owned = path.to_path_buf();
+    thread::Builder::new()
+        .name("qmpo-probe".to_string())
+        .spawn(move || {
+            let _ = tx.send(probe(&owned));
+        })
+        .map_err(QmpoError::Io)?;
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result.map_err(|e| QmpoError::from_io(e, path)),
+        Err(_) => Err(QmpoError::Timeout(unreachable_message(path, timeout))),
+    }
+}
+
+fn unreachable_message(path: &Path, timeout: Duration) -> String {
+    let server = server_host(path).unwrap_or_else(|| path.display().to_string());
+    format!(
+        "server unreachable: {server} (no answer within {} ms)",
+        timeout.as_millis()
+    )
+}
+
+/// The server a path lives on, if it is a UNC path or on a network mount.
+///
+/// Only reads `/proc/self/mountinfo`, which never blocks on the server itself.
+pub fn server_host(path: &Path) -> Option<String> {
+    if let Some(host) = unc_host(&path.to_string_lossy()) {
+        return Some(host);
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo") {
+        return mount_host(&mountinfo, path);
+    }
+    None
+}
+
+/// The host of a `\\host\share` or `//host/share` path.
+fn unc_host(path: &str) -> Option<String> {
+    let rest = path
+        .strip_prefix(r"\\")
+        .or_else(|| path.strip_prefix("//"))?;
+    let host = rest.split(['\\', '/']).next()?;
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// The server behind the network mount containing `path`, from mountinfo lines.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn mount_host(mountinfo: &str, path: &Path) -> Option<String> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            // <id> <parent> <dev> <root> <mount point> <options> ... - <type> <source> <options>
+            let (left, right) = line.split_once(" - ")?;
+            let mount_point = PathBuf::from(unescape(left.split(' ').nth(4)?));
+            let mut right = right.split(' ');
+            let fstype = right.next()?;
+            let source = unescape(right.next()?);
+            path.starts_with(&mount_point)
+                .then_some((mount_point, fstype.to_string(), source))
+        })
+        .max_by_key(|(mount_point, _, _)| mount_point.components().count())
+        .and_then(|(_, fstype, source)| source_host(&fstype, &source))
+}
+
+/// The host named in a mount source, for network filesystem types.
+fn source_host(fstype: &str, source: &str) -> Option<String> {
+    match fstype {
+        "cifs" | "smb3" | "smbfs" => unc_host(source),
+        "nfs" | "nfs4" | "fuse.sshfs" => {
+            let (host, _) = source.split_once(':')?;
+            let host = host.rsplit('@').next()?;
+            (!host.is_empty()).then(|| host.trim_matches(['[', ']']).to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Undo the octal escapes (`\040` for space) used in mountinfo fields.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw
+40 22 0:50 / /mnt/share rw,relatime - cifs //fileserver/share rw,vers=3.1.1
+41 22 0:51 / /mnt/nfs rw,relatime - nfs4 nas.example.com:/export rw
+42 40 0:52 / /mnt/share/My\\040Docs rw,relatime - cifs //docs/home rw
+43 22 0:53 / /mnt/ssh rw - fuse.sshfs alice@build:/home/alice rw
+";
+
+    #[test]
+    fn test_probe_existing_path() {
+        let dir = std::env::temp_dir();
+        let probed = probe(&dir, Duration::from_secs(5)).unwrap();
+        assert_eq!(probed.path, dir.canonicalize().unwrap());
+        assert!(!probed.is_file);
+
+        let err = probe(Path::new("/nonexistent/qmpo-probe"), Duration::from_secs(5)).unwrap_err();
+        assert!(matches!(err, QmpoError::NotFound(_)));
+    }
+
+    #[test]
+    fn test_probe_times_out() {
+        let err = probe_with::<Probed, _>(
+            Path::new(r"\\fileserver\share\HR"),
+            Duration::from_millis(10),
+            |_| {
+                thread::sleep(Duration::from_secs(1));
+                Err(io::ErrorKind::Other.into())
+            },
+        )
+        .unwrap_err();
+        match err {
+            QmpoError::Timeout(message) => {
+                assert_eq!(
+                    message,
+                    "server unreachable: fileserver (no answer within 10 ms)"
+                );
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_unc_host() {
+        assert_eq!(unc_host(r"\\fileserver\share\a"), Some("fileserver".into()));
+        assert_eq!(unc_host("//fileserver/share"), Some("fileserver".into()));
+        assert_eq!(unc_host("/srv/share"), None);
+        assert_eq!(unc_host(r"\\"), None);
+    }
+
+    #[test]
+    fn test_mount_host() {
+        let host = |path: &str| mount_host(MOUNTINFO, Path::new(path));
+        assert_eq!(host("/mnt/share/HR/a.txt"), Some("fileserver".into()));
+        assert_eq!(host("/mnt/nfs/data"), Some("nas.example.com".into()));
+        assert_eq!(host("/mnt/share/My Docs/x"), Some("docs".into()));
+        assert_eq!(host("/mnt/ssh"), Some("build".into()));
+        // Local filesystems and partial component matches have no server
+        assert_eq!(host("/home/alice"), None);
+        assert_eq!(host("/mnt/shared"), None);
+    }
+}
@@ -0,0 +1,432 @@
+//! The open pipeline, from URI to launcher commands.
+//!
+//! [`resolve`] runs every stage except the launch itself and records what each
+//! stage did, so the same code serves both opening a URI and `qmpo resolve`.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{QmpoError, Result};
+use crate::history::Action;
+use crate::launcher::{self, Plan};
+use crate::log::{self, Level};
+use crate::lookup::{self, Lookup};
+use crate::probe::{self, OnTimeout, Probed};
+use crate::uri::{self, DirectoryUri};
+use crate::{mapping, placeholder, privacy, signing};
+
+/// What one pipeline stage did.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Stage {
+    pub stage: &'static str,
+    pub detail: String,
+}
+
+/// The outcome of a successful resolution.
+#[derive(Debug)]
+pub struct Resolution {
+    /// The path after mappings, resolved to its canonical form
+    /// (or as given, when the probe timed out and `on_timeout = "passthrough"`).
+    pub path: PathBuf,
+    pub action: Action,
+    pub plan: Plan,
+}
+
+/// Run the pipeline for `uri_str` without launching anything.
+///
+/// Stages are appended to `stages` as they complete, so a failed resolution
+/// still shows how far it got.
+pub fn resolve(
+    uri_str: &str,
+    config: &Config,
+    now: u64,
+    stages: &mut Vec<Stage>,
+) -> Result<Resolution> {
+    let mut record = |stage, detail: String| stages.push(Stage { stage, detail });
+
+    let hostname = if config.uri.local_hostname {
+        uri::hostname()
+    } else {
+        None
+    };
+    let uri = DirectoryUri::parse_with(uri_str, &config.uri.parse_options(hostname.as_deref()))?;
+    let path = uri.path();
+    // Redaction only knows the plain reading of the URI, not its fallback
+    // encoding or a local host name stripped by the parse options
+    privacy::track_path(path);
+    match uri.encoding() {
+        Some(encoding) => {
+            log::log(
+                Level::Info,
+                "Decoded path with fallback encoding",
+                &[("encoding", encoding.name())],
+            );
+            record(
+                "parse",
+                format!("{} (decoded as {})", path.display(), encoding.name()),
+            );
+        }
+        None => record("parse", path.display().to_string()),
+    }
+    if let Some(webdav) = uri.webdav() {
+        record("parse", format!("WebDAV folder {}", webdav.url()));
+    }
+
+    record(
+        "options",
+        match uri.signature() {
+            Some(signature) => format!("signed, expires {}", signature.exp),
+            None => "none".to_string(),
+        },
+    );
+
+    // Placeholders name a different location for every user; mappings and
+    // the policy apply to where they point on this machine
+    let expanded;
+    let path = match uri.placeholder() {
+        Some(placeholder) => {
+            expanded = placeholder.expand(&config.placeholders, placeholder::locate)?;
+            privacy::track_path(&expanded);
+            record(
+                "expand",
+                format!("{} (from {})", expanded.display(), placeholder.base),
+            );
+            expanded.as_path()
+        }
+        None => path,
+    };
+
+    let path = match mapping::apply(&config.mappings, path) {
+        Some((mapped, used)) => {
+            privacy::track_path(&mapped);
+            record(
+                "mapping",
+                format!("{} (from {})", mapped.display(), used.from),
+            );
+            mapped
+        }
+        None => match wsl_path(path) {
+            Some(translated) => {
+                privacy::track_path(&translated);
+                record("mapping", format!("{} (WSL)", translated.display()));
+                translated
+            }
+            None => {
+                record("mapping", "none".to_string());
+                path.to_path_buf()
+            }
+        },
+    };
+
+    // Check the policy before touching the filesystem, so refused links
+    // never reach a network share
+    signing::verify(&uri, &config.signing, now)?;
+    record(
+        "policy",
+        match (uri.signature(), &config.signing.key) {
+            (Some(_), Some(_)) => "signature valid",
+            _ => "unsigned link allowed",
+        }
+        .to_string(),
+    );
+
+    // Windows drive and UNC paths cannot be opened as-is elsewhere
+    if !cfg!(target_os = "windows") && !path.has_root() {
+        return Err(QmpoError::MappingFailed(path));
+    }
+
+    // Canonicalize to resolve symlinks and prevent path traversal attacks.
+    // An unreachable server would block here, so the probe has a deadline.
+    let probed = match probe::probe(&path, config.probe.timeout()) {
+        Err(QmpoError::NotFound(_)) if config.lookup.is_enabled() => {
+            lookup_fallback(&path, config, &mut record)
+        }
+        probed => probed,
+    };
+    let (path, action) = match probed {
+        Ok(probed) => {
+            privacy::track_path(&probed.path);
+            record("canonicalize", probed.path.display().to_string());
+            (probed.path, Action::for_file(probed.is_file))
+        }
+        Err(QmpoError::Timeout(message)) if config.probe.on_timeout == OnTimeout::Passthrough => {
+            record("canonicalize", format!("{message}; passing path through"));
+            (path, Action::Open)
+        }
+        Err(e) => return Err(e),
+    };
+    record("route", action.as_str().to_string());
+
+    let plan = launcher::plan(&path, action, launcher::backend(config))?;
+    Ok(Resolution { path, action, plan })
+}
+
+/// The Linux path of a Windows path, when running in WSL.
+#[cfg(target_os = "linux")]
+fn wsl_path(path: &Path) -> Option<PathBuf> {
+    crate::wsl::current()?.to_linux(path.to_str()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn wsl_path(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Look for a respelling of a missing path, under the same deadline as the probe.
+fn lookup_fallback(
+    path: &Path,
+    config: &Config,
+    record: &mut impl FnMut(&'static str, String),
+) -> Result<Probed> {
+    let lookup_config = config.lookup.clone();
+    let lookup = probe::probe_with(path, config.probe.timeout(), move |path| {
+        lookup::find(path, &lookup_config)
+    })?;
+    match lookup {
+        Lookup::Found {
+            path: found,
+            substituted: true,
+        } => {
+            privacy::track_path(&found);
+            log::log(
+                Level::Info,
+                "Substituted path spelling",
+                &[
+                    ("path", &path.display().to_string()),
+                    ("found", &found.display().to_string()),
+                ],
+            );
+            record(
+                "lookup",
+                format!("{} (from {})", found.display(), path.display()),
+            );
+            probe::probe(&found, config.probe.timeout())
+        }
+        // Found unchanged: the path exists but does not canonicalize (e.g. a broken symlink)
+        Lookup::Missing | Lookup::Found { .. } => {
+            record("lookup", "no match".to_string());
+            Err(QmpoError::NotFound(path.to_path_buf()))
+        }
+        Lookup::Ambiguous { dir, candidates } => {
+            // The sibling names are as private as the path itself
+            privacy::track_path(&dir);
+            let candidates = candidates
+                .iter()
+                .map(|candidate| {
+                    let candidate = dir.join(candidate);
+                    privacy::track_path(&candidate);
+                    candidate.display().to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            log::log(
+                Level::Warn,
+                "Ambiguous path spelling refused",
+                &[
+                    ("path", &path.display().to_string()),
+                    ("candidates", &candidates),
+                ],
+            );
+            record(
+                "lookup",
+                format!("ambiguous in {}: {candidates}", dir.display()),
+            );
+            Err(QmpoError::NotFound(path.to_path_buf()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapping::Mapping;
+    use std::fs;
+    use std::sync::Mutex;
+
+    /// Held by tests that install a global redaction mode.
+    static REDACTION: Mutex<()> = Mutex::new(());
+
+    fn names(stages: &[Stage]) -> Vec<&str> {
+        stages.iter().map(|s| s.stage).collect()
+    }
+
+    #[test]
+    fn test_resolve_directory_with_mapping() {
+        let dir = std::env::temp_dir().join("qmpo_test_resolve");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("HR")).unwrap();
+
+        let config = Config {
+            mappings: vec![Mapping {
+                from: r"\\fileserver\share".to_string(),
+                to: dir.display().to_string(),
+            }],
+            ..Config::default()
+        };
+        let mut stages = Vec::new();
+        let resolution =
+            resolve("directory://fileserver/share/HR", &config, 0, &mut stages).unwrap();
+
+        assert_eq!(
+            names(&stages),
+            [
+                "parse",
+                "options",
+                "mapping",
+                "policy",
+                "canonicalize",
+                "route"
+            ]
+        );
+        assert_eq!(resolution.path, dir.join("HR").canonicalize().unwrap());
+        assert_eq!(resolution.action, Action::Open);
+        assert!(!resolution.plan.steps.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_resolution_keeps_stages() {
+        let mut stages = Vec::new();
+        let err = resolve(
+            "directory:///nonexistent/qmpo-resolve",
+            &Config::default(),
+            0,
+            &mut stages,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, QmpoError::NotFound(_)));
+        assert_eq!(names(&stages), ["parse", "options", "mapping", "policy"]);
+    }
+
+    #[test]
+    fn test_placeholder_expanded_before_policy() {
+        let Some(home) = placeholder::locate(&placeholder::Base::Home) else {
+            return;
+        };
+        let mut stages = Vec::new();
+        let err = resolve(
+            "directory:///~/qmpo-resolve-missing",
+            &Config::default(),
+            0,
+            &mut stages,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, QmpoError::NotFound(path) if path == home.join("qmpo-resolve-missing"))
+        );
+        assert_eq!(
+            names(&stages),
+            ["parse", "options", "expand", "mapping", "policy"]
+        );
+
+        // Environment variables must be allowed first
+        let mut stages = Vec::new();
+        let err =
+            resolve("directory://env/HOME/x", &Config::default(), 0, &mut stages).unwrap_err();
+        assert!(matches!(err, QmpoError::PolicyDenied(_)));
+    }
+
+    // Other platforms' filesystems already ignore case
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_lookup_fallback_respells_path() {
+        let dir =
+            std::env::temp_dir().join(format!("qmpo_test_resolve_lookup_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Reports")).unwrap();
+        let uri = DirectoryUri::from_path(&dir.join("REPORTS"))
+            .unwrap()
+            .to_string();
+
+        let mut stages = Vec::new();
+        let err = resolve(&uri, &Config::default(), 0, &mut stages).unwrap_err();
+        assert!(matches!(err, QmpoError::NotFound(_)));
+
+        let mut config = Config::default();
+        config.lookup.ignore_case = true;
+        let mut stages = Vec::new();
+        let resolution = resolve(&uri, &config, 0, &mut stages).unwrap();
+        assert_eq!(resolution.path, dir.join("Reports").canonicalize().unwrap());
+        assert!(names(&stages).contains(&"lookup"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ambiguous_lookup_is_redacted() {
+        let dir = std::env::temp_dir().join(format!(
+            "qmpo_test_resolve_ambiguous_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Medical")).unwrap();
+        fs::create_dir_all(dir.join("MEDICAL")).unwrap();
+        let uri = DirectoryUri::from_path(&dir.join("medical"))
+            .unwrap()
+            .to_string();
+
+        let mut config = Config::default();
+        config.lookup.ignore_case = true;
+        config.privacy.redact = privacy::Redaction::Truncate;
+        config.privacy.components = 1;
+        let _guard = REDACTION.lock().unwrap_or_else(|e| e.into_inner());
+        privacy::init(&config.privacy).unwrap();
+        let mut stages = Vec::new();
+        let err = resolve(&uri, &config, 0, &mut stages).unwrap_err();
+        let lookup = stages.iter().find(|s| s.stage == "lookup").unwrap();
+        let scrubbed = privacy::scrub(&lookup.detail);
+        privacy::init(&Default::default()).unwrap();
+
+        assert!(matches!(err, QmpoError::NotFound(_)));
+        assert!(lookup.detail.contains("Medical"), "{}", lookup.detail);
+        assert!(!scrubbed.contains("Medical"), "{scrubbed}");
+        assert!(!scrubbed.contains("MEDICAL"), "{scrubbed}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_own_hostname_path_is_redacted() {
+        let Some(hostname) = uri::hostname() else {
+            return;
+        };
+        let mut config = Config::default();
+        config.uri.local_hostname = true;
+        config.privacy.redact = privacy::Redaction::Truncate;
+        config.privacy.components = 1;
+        let _guard = REDACTION.lock().unwrap_or_else(|e| e.into_inner());
+        privacy::init(&config.privacy).unwrap();
+        let mut stages = Vec::new();
+        let err = resolve(
+            &format!("directory://{hostname}/nonexistent/qmpo-hostname-secret"),
+            &config,
+            0,
+            &mut stages,
+        )
+        .unwrap_err();
+        let scrubbed = privacy::scrub(&err.to_string());
+        privacy::init(&Default::default()).unwrap();
+
+        assert!(matches!(err, QmpoError::NotFound(_)));
+        assert!(!scrubbed.contains("qmpo-hostname-secret"), "{scrubbed}");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_unmapped_windows_path() {
+        let mut stages = Vec::new();
+        let err = resolve(
+            "directory://fileserver/share",
+            &Config::default(),
+            0,
+            &mut stages,
+        )
+        .unwrap_err();
+        assert!(matches!(err, QmpoError::MappingFailed(_)));
+    }
+}
@@ -0,0 +1,299 @@
+//! Hardened mode: confining qmpo itself on Linux.
+//!
+//! URIs come from arbitrary web pages, yet once the configuration is loaded
+//! qmpo only needs to stat paths, write its own log and history, and start
+//! `dbus-send`. With `[sandbox] enabled = true`, it restricts itself before
+//! handling any URI:
+//!
+//! - Landlock lets qmpo list any directory but read only system files
+//!   (`/usr`, `/etc`, ...) and its own config; it can write only to the data
+//!   and runtime directories and `/dev/null`. Stat-ing paths is never
+//!   restricted, so the contents of `~/.ssh` and other user files stay out of
+//!   reach while any path can still be resolved
+//! - a seccomp filter makes system calls that qmpo never needs (`ptrace`,
+//!   `mount`, `unshare`, `bpf`, module loading, ...) fail with `EPERM`
+//!
+//! Both restrictions are inherited by launched programs, so hardened mode
+//! starts file managers through D-Bus activation instead of spawning them
+//! (see [`launcher::backend`](crate::launcher::backend)). Kernels without
+//! Landlock or seccomp keep working; the missing restriction is logged.
+
+use serde::Deserialize;
+
+use crate::log::{self, Level};
+
+/// Settings for the `[sandbox]` config section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Confine qmpo with Landlock and seccomp (Linux only).
+    pub enabled: bool,
+}
+
+/// Confine the process if hardened mode is enabled.
+///
+/// Never fails: restrictions the system does not support are logged and skipped.
+pub fn enter(config: &SandboxConfig) {
+    if !config.enabled {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let writable = linux::writable_paths();
+        report(
+            "Landlock",
+            linux::restrict_filesystem(&linux::readable_paths(), &writable),
+        );
+        report("seccomp", linux::restrict_syscalls());
+    }
+    #[cfg(not(target_os = "linux"))]
+    log::log(
+        Level::Warn,
+        "Sandbox is not supported on this platform",
+        &[],
+    );
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn report(name: &str, result: Result<bool, String>) {
+    match result {
+        Ok(true) => log::log(Level::Debug, "Sandbox enforced", &[("layer", name)]),
+        Ok(false) => log::log(
+            Level::Warn,
+            "Sandbox layer not supported by this kernel",
+            &[("layer", name)],
+        ),
+        Err(e) => log::log(
+            Level::Warn,
+            "Sandbox layer failed",
+            &[("layer", name), ("error", &e)],
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use directories::{BaseDirs, ProjectDirs};
+    use landlock::{
+        ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+        path_beneath_rules,
+    };
+    use seccompiler::{SeccompAction, SeccompFilter, TargetArch};
+
+    use crate::config;
+
+    /// Newest Landlock ABI requested; older kernels get what they support.
+    const LANDLOCK_ABI: ABI = ABI::V5;
+
+    /// System calls refused with `EPERM`.
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_reboot,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+        libc::SYS_fanotify_init,
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        libc::SYS_personality,
+        libc::SYS_syslog,
+        libc::SYS_vhangup,
+    ];
+
+    /// System locations qmpo and `dbus-send` read or execute: programs,
+    /// libraries, time zones, `/etc`, and the `/proc` entries qmpo inspects.
+    const SYSTEM_READABLE: &[&str] = &[
+        "/usr",
+        "/bin",
+        "/sbin",
+        "/lib",
+        "/lib32",
+        "/lib64",
+        "/etc",
+        "/var/lib/dbus",
+        "/proc/self",
+        "/proc/sys/fs/binfmt_misc",
+    ];
+
+    /// Files qmpo may read besides its writable directories: system locations,
+    /// its config directory, and `user-dirs.dirs` for placeholders.
+    pub fn readable_paths() -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = SYSTEM_READABLE.iter().map(PathBuf::from).collect();
+        if let Some(dirs) = ProjectDirs::from("", "", "qmpo") {
+            paths.push(dirs.config_dir().to_path_buf());
+        }
+        if let Some(dirs) = BaseDirs::new() {
+            paths.push(dirs.config_dir().join("user-dirs.dirs"));
+        }
+        paths
+    }
+
+    /// Directories qmpo writes to, created first so they can be allowed.
+    pub fn writable_paths() -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from("/dev/null")];
+        if let Some(dirs) = ProjectDirs::from("", "", "qmpo") {
+            let _ = std::fs::create_dir_all(dirs.data_dir());
+            paths.push(dirs.data_dir().to_path_buf());
+        }
+        if let Ok(dir) = config::runtime_dir() {
+            paths.push(dir);
+        }
+        paths
+    }
+
+    /// Allow listing every directory, reading and executing beneath
+    /// `readable`, and everything beneath `writable`.
+    ///
+    /// Returns whether Landlock is enforced.
+    pub fn restrict_filesystem(readable: &[PathBuf], writable: &[PathBuf]) -> Result<bool, String> {
+        let existing = |paths: &[PathBuf]| -> Vec<PathBuf> {
+            paths.iter().filter(|path| path.exists()).cloned().collect()
+        };
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+            .and_then(|ruleset| ruleset.create())
+            .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/"], AccessFs::ReadDir)))
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    existing(readable),
+                    AccessFs::from_read(LANDLOCK_ABI),
+                ))
+            })
+            .and_then(|ruleset| {
+                ruleset.add_rules(path_beneath_rules(
+                    existing(writable),
+                    AccessFs::from_all(LANDLOCK_ABI),
+                ))
+            })
+            .and_then(|ruleset| ruleset.restrict_self())
+            .map_err(|e| e.to_string())?;
+        Ok(status.ruleset != RulesetStatus::NotEnforced)
+    }
+
+    /// Install the seccomp filter on every thread.
+    ///
+    /// Returns `Ok(false)` on architectures the filter compiler does not know.
+    pub fn restrict_syscalls() -> Result<bool, String> {
+        let Ok(arch) = TargetArch::try_from(std::env::consts::ARCH) else {
+            return Ok(false);
+        };
+        // `c_long` is narrower than `i64` on 32-bit targets
+        #[allow(clippy::useless_conversion)]
+        let rules = DENIED_SYSCALLS
+            .iter()
+            .map(|&nr| (i64::from(nr), Vec::new()))
+            .collect::<BTreeMap<_, _>>();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            arch,
+        )
+        .map_err(|e| e.to_string())?;
+        let program = seccompiler::BpfProgram::try_from(filter).map_err(|e| e.to_string())?;
+        seccompiler::apply_filter_all_threads(&program).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Set in the child process that applies the sandbox.
+    const CHILD_ENV: &str = "QMPO_TEST_SANDBOX_DIR";
+
+    /// The sandbox cannot be lifted, so it is tested in a child process that
+    /// runs only this test.
+    #[test]
+    fn test_sandbox_blocks_writes_and_syscalls() {
+        if let Some(dir) = std::env::var_os(CHILD_ENV) {
+            sandboxed_child(dir.into());
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("qmpo_test_sandbox_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("allowed")).unwrap();
+        fs::write(dir.join("secret"), "secret").unwrap();
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "sandbox::tests::test_sandbox_blocks_writes_and_syscalls",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD_ENV, &dir)
+            .output()
+            .unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    fn sandboxed_child(dir: std::path::PathBuf) {
+        let allowed = dir.join("allowed");
+        let enforced =
+            linux::restrict_filesystem(&linux::readable_paths(), std::slice::from_ref(&allowed))
+                .unwrap();
+        let filtered = linux::restrict_syscalls().unwrap();
+
+        fs::write(allowed.join("ok"), "ok").unwrap();
+        if enforced {
+            let err = fs::write(dir.join("forbidden"), "x").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+            // User files can be found but not read
+            let err = fs::read(dir.join("secret")).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        } else {
+            println!("Landlock not supported by this kernel; access checks skipped");
+        }
+        // Stat-ing, listing and reading system files stay allowed
+        assert!(fs::metadata(dir.join("secret")).unwrap().is_file());
+        assert!(fs::read_dir(&dir).unwrap().count() >= 2);
+        assert!(fs::read("/etc/hostname").is_ok() || !Path::new("/etc/hostname").exists());
+
+        if filtered {
+            // SAFETY: unshare with no flags has no effect even when allowed
+            let rc = unsafe { libc::unshare(0) };
+            assert_eq!(rc, -1);
+            assert_eq!(
+                std::io::Error::last_os_error().raw_os_error(),
+                Some(libc::EPERM)
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Per-install secrets kept in the data directory.
+//!
+//! Each secret is 32 random bytes, hex-encoded, in a file readable only by the
+//! user (`token` for the HTTP bridge, `privacy.key` for path hashing).
+//!
+//! A new secret is written to a temporary file and then linked into place, so
+//! other processes see either no file or the complete secret, and the first
+//! process to link wins.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use directories::ProjectDirs;
+
+/// Number of random bytes in a freshly generated secret.
+const SECRET_BYTES: usize = 32;
+
+/// How often an existing but empty secret file is read again before giving up.
+const EMPTY_RETRIES: u32 = 5;
+
+/// Read the secret stored in `name`, generating it on first use.
+pub fn load_or_create(name: &str) -> io::Result<String> {
+    let path = ProjectDirs::from("", "", "qmpo")
+        .map(|dirs| dirs.data_dir().join(name))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "could not determine data directory"))?;
+    load_or_create_at(&path)
+}
+
+fn load_or_create_at(path: &Path) -> io::Result<String> {
+    let mut retries = 0;
+    loop {
+        match fs::read_to_string(path) {
+            Ok(secret) if !secret.trim().is_empty() => return Ok(secret.trim().to_string()),
+            // Only an older qmpo could leave an empty file behind; it is never
+            // removed here, since it may be another process's secret in the making
+            Ok(_) if retries < EMPTY_RETRIES => {
+                retries += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("secret file is empty: {}", path.display()),
+                ));
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if let Some(secret) = create(path)? {
+                    return Ok(secret);
+                }
+                // Another process created it first; read theirs
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Generate a secret and link it into place at `path`.
+///
+/// Returns `None` if `path` already exists.
+fn create(path: &Path) -> io::Result<Option<String>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut bytes = [0u8; SECRET_BYTES];
+    getrandom::fill(&mut bytes).map_err(io::Error::other)?;
+    let secret: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+
+    // Unique per process and thread, so concurrent creators never share it
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = Path::new(&tmp);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options.open(tmp).and_then(|mut file| {
+        file.write_all(secret.as_bytes())?;
+        file.sync_all()
+    });
+    // Unlike a rename, a hard link never replaces an existing secret
+    let linked = written.and_then(|()| fs::hard_link(tmp, path));
+    let _ = fs::remove_file(tmp);
+    match linked {
+        Ok(()) => Ok(Some(secret)),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_creation_agrees() {
+        let dir = std::env::temp_dir().join(format!("qmpo_test_secret_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("token");
+
+        let secrets: Vec<String> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| load_or_create_at(&path).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(secrets[0].len(), SECRET_BYTES * 2);
+        assert!(secrets.iter().all(|secret| *secret == secrets[0]));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_empty_secret_is_kept_and_refused() {
+        let dir =
+            std::env::temp_dir().join(format!("qmpo_test_secret_empty_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token");
+        fs::write(&path, "\n").unwrap();
+
+        let err = load_or_create_at(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,539 @@
+//! Local HTTP bridge for browsers that cannot use custom URI schemes.
+//!
+//! `qmpo serve` listens on `127.0.0.1` only and exposes two endpoints:
+//!
+//! - `GET /ping` - lets a web page detect that qmpo is installed
+//! - `POST /open` - opens the `directory://` URI sent as the request body
+//!
+//! `POST /open` requires the per-install token in the `X-Qmpo-Token` header.
+//! Requests carrying an `Origin` header are rejected unless the origin is
+//! listed in `serve.allowed_origins`.
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::config::ServeConfig;
+use crate::error::QmpoError;
+use crate::log;
+use crate::secret;
+
+/// Maximum size of the request line and headers.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+/// Maximum size of a request body.
+const MAX_BODY_SIZE: usize = 8 * 1024;
+/// Read/write timeout for a single connection.
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Header carrying the per-install token (lowercase for lookup).
+const TOKEN_HEADER: &str = "x-qmpo-token";
+
+/// Callback that opens a URI, given the request's `Origin` header if any.
+type OpenFn<'a> = dyn Fn(&str, Option<&str>) -> Result<(), QmpoError> + 'a;
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    /// Header names are stored lowercase.
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: value.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "ok": false, "error": message }))
+    }
+}
+
+/// Request routing and access control for the bridge.
+struct Bridge<'a> {
+    port: u16,
+    token: &'a str,
+    allowed_origins: &'a [String],
+}
+
+impl Bridge<'_> {
+    fn handle(&self, req: &Request, open: &OpenFn<'_>) -> Response {
+        // Reject DNS rebinding: the Host header must name the loopback listener.
+        let host_ok = req.header("host").is_some_and(|host| {
+            host == format!("127.0.0.1:{}", self.port) || host == format!("localhost:{}", self.port)
+        });
+        if !host_ok {
+            return Response::error(403, "invalid host");
+        }
+
+        // Requests without an Origin header come from non-browser tools and are
+        // still subject to the token check below.
+        let origin = req.header("origin");
+        if let Some(origin) = origin
+            && !self.allowed_origins.iter().any(|allowed| allowed == origin)
+        {
+            return Response::error(403, "origin not allowed");
+        }
+
+        let path = req.path.split('?').next().unwrap_or_default();
+        let mut response = match (req.method.as_str(), path) {
+            ("OPTIONS", "/ping" | "/open") => self.preflight(req),
+            ("GET", "/ping") => Response::json(
+                200,
+                json!({ "app": "qmpo", "version": env!("CARGO_PKG_VERSION") }),
+            ),
+            ("POST", "/open") => self.open(req, open),
+            (_, "/ping" | "/open") => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "not found"),
+        };
+
+        if let Some(origin) = origin {
+            response
+                .headers
+                .push(("Access-Control-Allow-Origin", origin.to_string()));
+            response.headers.push(("Vary", "Origin".to_string()));
+        }
+        response
+    }
+
+    fn preflight(&self, req: &Request) -> Response {
+        let mut response = Response::new(204);
+        response
+            .headers
+            .push(("Access-Control-Allow-Methods", "GET, POST".to_string()));
+        response.headers.push((
+            "Access-Control-Allow-Headers",
+            "Content-Type, X-Qmpo-Token".to_string(),
+        ));
+        response
+            .headers
+            .push(("Access-Control-Max-Age", "600".to_string()));
+        // Chrome's Private Network Access asks before a public page may reach localhost
+        if req.header("access-control-request-private-network") == Some("true") {
+            response
+                .headers
+                .push(("Access-Control-Allow-Private-Network", "true".to_string()));
+        }
+        response
+    }
+
+    fn open(&self, req: &Request, open: &OpenFn<'_>) -> Response {
+        // An empty token would match an empty header
+        let token_ok = !self.token.is_empty()
+            && req
+                .header(TOKEN_HEADER)
+                .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()));
+        if !token_ok {
+            return Response::error(401, "missing or invalid token");
+        }
+
+        let uri = req.body.trim();
+        if uri.is_empty() {
+            return Response::error(400, "missing URI in request body");
+        }
+
+        match open(uri, req.header("origin")) {
+            Ok(()) => Response::json(200, json!({ "ok": true })),
+            Err(e) => Response::json(
+                422,
+                json!({ "ok": false, "error": e.to_string(), "kind": e.kind(), "code": e.exit_code() }),
+            ),
+        }
+    }
+}
+
+/// Run the HTTP bridge until the process is terminated.
+///
+/// `open` is called with each accepted URI and its `Origin` header, and runs
+/// the same pipeline as the CLI.
+pub fn serve(config: &ServeConfig, open: &OpenFn<'_>) -> io::Result<()> {
+    let token = load_or_create_token()?;
+    if token.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the bridge token is empty",
+        ));
+    }
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, config.port))?;
+    let port = listener.local_addr()?.port();
+
+    let bridge = Bridge {
+        port,
+        token: &token,
+        allowed_origins: &config.allowed_origins,
+    };
+
+    log::info(&format!("HTTP bridge listening on 127.0.0.1:{port}"));
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve_connection(&bridge, stream, open));
+        if let Err(e) = result {
+            log::error(&format!("HTTP bridge connection failed: {e}"));
+        }
+    }
+    Ok(())
+}
+
+fn serve_connection(bridge: &Bridge, mut stream: TcpStream, open: &OpenFn<'_>) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let response = match read_request(&mut stream) {
+        Ok(req) => bridge.handle(&req, open),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Response::error(400, &e.to_string()),
+        Err(e) => return Err(e),
+    };
+    write_response(&mut stream, &response)
+}
+
+fn read_request(stream: impl Read) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream.take((MAX_HEADER_SIZE + MAX_BODY_SIZE) as u64));
+    let mut header_size = 0;
+
+    let mut read_line = |reader: &mut BufReader<_>| -> io::Result<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        header_size += line.len();
+        if header_size > MAX_HEADER_SIZE {
+            return Err(invalid_data("request headers too large"));
+        }
+        if !line.ends_with('\n') {
+            return Err(invalid_data("unexpected end of request"));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(path), Some(_version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid_data("malformed request line"));
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_data("malformed header"))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    let content_length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, value)) => value
+            .parse::<usize>()
+            .map_err(|_| invalid_data("invalid Content-Length"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid_data("request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid_data("request body is not UTF-8"))?;
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body,
+    })
+}
+
+fn write_response(mut stream: impl Write, response: &Response) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.body.len()
+    );
+    if !response.body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Entity",
+        _ => "",
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Read the per-install token, generating it on first use.
+pub fn load_or_create_token() -> io::Result<String> {
+    secret::load_or_create("token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: u16 = 47281;
+    const TOKEN: &str = "secret";
+
+    fn bridge(allowed_origins: &[String]) -> Bridge<'_> {
+        Bridge {
+            port: PORT,
+            token: TOKEN,
+            allowed_origins,
+        }
+    }
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        let mut all_headers = vec![("host".to_string(), format!("127.0.0.1:{PORT}"))];
+        all_headers.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: all_headers,
+            body: body.to_string(),
+        }
+    }
+
+    fn open_ok(_uri: &str, _origin: Option<&str>) -> Result<(), QmpoError> {
+        Ok(())
+    }
+
+    fn open_unreachable(_uri: &str, _origin: Option<&str>) -> Result<(), QmpoError> {
+        panic!("open must not be called");
+    }
+
+    #[test]
+    fn test_ping() {
+        let response = bridge(&[]).handle(&request("GET", "/ping", &[], ""), &open_unreachable);
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("\"app\":\"qmpo\""));
+    }
+
+    #[test]
+    fn test_open_with_token() {
+        let req = request(
+            "POST",
+            "/open",
+            &[(TOKEN_HEADER, TOKEN)],
+            "directory:///tmp\r\n",
+        );
+        let response = bridge(&[]).handle(&req, &|uri, origin| {
+            assert_eq!(uri, "directory:///tmp");
+            assert_eq!(origin, None);
+            Ok(())
+        });
+        assert_eq!(response.status, 200);
+    }
+
+    #[test]
+    fn test_open_reports_failure() {
+        let req = request("POST", "/open", &[(TOKEN_HEADER, TOKEN)], "directory:///x");
+        let response = bridge(&[]).handle(&req, &|_, _| {
+            Err(QmpoError::NotFound(std::path::PathBuf::from("/x")))
+        });
+        assert_eq!(response.status, 422);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(body["error"], "path does not exist: /x");
+        assert_eq!(body["kind"], "not_found");
+        assert_eq!(body["code"], 3);
+    }
+
+    #[test]
+    fn test_open_without_token() {
+        let req = request("POST", "/open", &[], "directory:///tmp");
+        let response = bridge(&[]).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 401);
+    }
+
+    #[test]
+    fn test_open_with_wrong_token() {
+        let req = request(
+            "POST",
+            "/open",
+            &[(TOKEN_HEADER, "secreT")],
+            "directory:///tmp",
+        );
+        let response = bridge(&[]).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 401);
+    }
+
+    #[test]
+    fn test_empty_token_accepts_nothing() {
+        let bridge = Bridge {
+            token: "",
+            ..bridge(&[])
+        };
+        let req = request("POST", "/open", &[(TOKEN_HEADER, "")], "directory:///tmp");
+        assert_eq!(bridge.handle(&req, &open_unreachable).status, 401);
+    }
+
+    #[test]
+    fn test_open_requires_post() {
+        let req = request("GET", "/open", &[(TOKEN_HEADER, TOKEN)], "");
+        let response = bridge(&[]).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 405);
+    }
+
+    #[test]
+    fn test_disallowed_origin() {
+        let allowed = vec!["https://intranet.example.com".to_string()];
+        let req = request(
+            "POST",
+            "/open",
+            &[
+                ("origin", "https://evil.example.com"),
+                (TOKEN_HEADER, TOKEN),
+            ],
+            "directory:///tmp",
+        );
+        let response = bridge(&allowed).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 403);
+    }
+
+    #[test]
+    fn test_allowed_origin_gets_cors_headers() {
+        let allowed = vec!["https://intranet.example.com".to_string()];
+        let req = request(
+            "GET",
+            "/ping",
+            &[("origin", "https://intranet.example.com")],
+            "",
+        );
+        let response = bridge(&allowed).handle(&req, &open_ok);
+        assert_eq!(response.status, 200);
+        assert!(response.headers.contains(&(
+            "Access-Control-Allow-Origin",
+            "https://intranet.example.com".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_preflight() {
+        let allowed = vec!["https://intranet.example.com".to_string()];
+        let req = request(
+            "OPTIONS",
+            "/open",
+            &[
+                ("origin", "https://intranet.example.com"),
+                ("access-control-request-private-network", "true"),
+            ],
+            "",
+        );
+        let response = bridge(&allowed).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 204);
+        assert!(
+            response
+                .headers
+                .iter()
+                .any(|(name, _)| *name == "Access-Control-Allow-Private-Network")
+        );
+    }
+
+    #[test]
+    fn test_rebinding_host_rejected() {
+        let mut req = request("GET", "/ping", &[], "");
+        req.headers[0].1 = format!("attacker.example.com:{PORT}");
+        let response = bridge(&[]).handle(&req, &open_unreachable);
+        assert_eq!(response.status, 403);
+    }
+
+    #[test]
+    fn test_unknown_path() {
+        let response = bridge(&[]).handle(&request("GET", "/", &[], ""), &open_unreachable);
+        assert_eq!(response.status, 404);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /open HTTP/1.1\r\nHost: 127.0.0.1:47281\r\nX-Qmpo-Token: abc\r\nContent-Length: 16\r\n\r\ndirectory:///tmp";
+        let req = read_request(&raw[..]).unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/open");
+        assert_eq!(req.header(TOKEN_HEADER), Some("abc"));
+        assert_eq!(req.body, "directory:///tmp");
+    }
+
+    #[test]
+    fn test_read_request_body_too_large() {
+        let raw = format!(
+            "POST /open HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        let err = read_request(raw.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_request_truncated() {
+        let err = read_request(&b"GET /ping HTTP/1.1\r\nHost: x"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Signed, expiring `directory://` links.
+//!
+//! A signed link carries `exp=<unix time>&sig=<hex>` in its query. The signature
+//! is an HMAC-SHA256 over the normalized target ([`DirectoryUri::target`]) and the
+//! expiry, keyed with `signing.key` from the system configuration.
+
+use std::path::Path;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::{SigningConfig, SigningMode};
+use crate::error::{QmpoError, Result};
+use crate::uri::{DirectoryUri, Signature};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Check a parsed URI against the signing policy.
+///
+/// Without a configured key, signatures are ignored unless the mode is
+/// `signed-only`, in which case every link is refused.
+pub fn verify(uri: &DirectoryUri, config: &SigningConfig, now: u64) -> Result<()> {
+    let Some(key) = &config.key else {
+        return match config.mode {
+            SigningMode::SignedOnly => Err(QmpoError::PolicyDenied(
+                "signed-only mode requires signing.key".to_string(),
+            )),
+            SigningMode::Optional => Ok(()),
+        };
+    };
+
+    let Some(signature) = uri.signature() else {
+        return match config.mode {
+            SigningMode::SignedOnly => {
+                Err(QmpoError::PolicyDenied("unsigned link refused".to_string()))
+            }
+            SigningMode::Optional => Ok(()),
+        };
+    };
+
+    let sig = decode_hex(&signature.sig)
+        .ok_or_else(|| QmpoError::PolicyDenied("malformed signature".to_string()))?;
+
+    mac(key.as_bytes(), &uri.target(), signature.exp)
+        .verify_slice(&sig)
+        .map_err(|_| QmpoError::PolicyDenied("invalid signature".to_string()))?;
+
+    if signature.exp < now {
+        return Err(QmpoError::PolicyDenied("link expired".to_string()));
+    }
+    Ok(())
+}
+
+/// Build a signed URI for `path` that expires `ttl` after `now`.
+pub fn sign(path: &Path, ttl: Duration, key: &str, now: u64) -> Result<DirectoryUri> {
+    let uri = DirectoryUri::from_path(path)?;
+    let exp = now.saturating_add(ttl.as_secs());
+    let sig = encode_hex(
+        &mac(key.as_bytes(), &uri.target(), exp)
+            .finalize()
+            .into_bytes(),
+    );
+    Ok(uri.with_signature(Signature { sig, exp }))
+}
+
+fn mac(key: &[u8], target: &str, exp: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(target.as_bytes());
+    mac.update(b"\n");
+    mac.update(exp.to_string().as_bytes());
+    mac
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "test-signing-key";
+    const NOW: u64 = 1_700_000_000;
+
+    fn config(mode: SigningMode) -> SigningConfig {
+        SigningConfig {
+            key: Some(KEY.to_string()),
+            mode,
+        }
+    }
+
+    fn signed(path: &str, ttl: u64) -> DirectoryUri {
+        let uri = sign(Path::new(path), Duration::from_secs(ttl), KEY, NOW).unwrap();
+        DirectoryUri::parse(&uri.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_signed_link_verifies() {
+        let uri = signed("/srv/share/team", 3600);
+        assert!(verify(&uri, &config(SigningMode::SignedOnly), NOW).is_ok());
+    }
+
+    #[test]
+    fn test_signed_unc_link_verifies() {
+        let uri = signed("\\\\server\\share\\Team Folder", 3600);
+        assert!(verify(&uri, &config(SigningMode::SignedOnly), NOW).is_ok());
+    }
+
+    #[test]
+    fn test_equivalent_spelling_verifies() {
+        let uri = signed("C:\\Users\\tagawa", 3600);
+        let sig = uri.signature().unwrap();
+        let respelled = DirectoryUri::parse(&format!(
+            "directory://C/Users/tagawa?exp={}&sig={}",
+            sig.exp, sig.sig
+        ))
+        .unwrap();
+        assert!(verify(&respelled, &config(SigningMode::SignedOnly), NOW).is_ok());
+    }
+
+    #[test]
+    fn test_expired_link_refused() {
+        let uri = signed("/srv/share", 60);
+        let result = verify(&uri, &config(SigningMode::Optional), NOW + 61);
+        assert!(matches!(result, Err(QmpoError::PolicyDenied(msg)) if msg == "link expired"));
+    }
+
+    #[test]
+    fn test_tampered_path_refused() {
+        let uri = signed("/srv/share/public", 3600);
+        let sig = uri.signature().unwrap().clone();
+        let tampered = DirectoryUri::parse("directory:///srv/share/private")
+            .unwrap()
+            .with_signature(sig);
+        let result = verify(&tampered, &config(SigningMode::Optional), NOW);
+        assert!(matches!(result, Err(QmpoError::PolicyDenied(msg)) if msg == "invalid signature"));
+    }
+
+    #[test]
+    fn test_extended_expiry_refused() {
+        let uri = signed("/srv/share", 60);
+        let mut sig = uri.signature().unwrap().clone();
+        sig.exp += 3600;
+        let tampered = uri.with_signature(sig);
+        assert!(verify(&tampered, &config(SigningMode::Optional), NOW + 120).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_refused() {
+        let uri = signed("/srv/share", 3600);
+        let config = SigningConfig {
+            key: Some("another-key".to_string()),
+            mode: SigningMode::Optional,
+        };
+        assert!(verify(&uri, &config, NOW).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_link() {
+        let uri = DirectoryUri::parse("directory:///srv/share").unwrap();
+        assert!(verify(&uri, &config(SigningMode::Optional), NOW).is_ok());
+        assert!(verify(&uri, &config(SigningMode::SignedOnly), NOW).is_err());
+    }
+
+    #[test]
+    fn test_signed_only_without_key() {
+        let uri = signed("/srv/share", 3600);
+        let config = SigningConfig {
+            key: None,
+            mode: SigningMode::SignedOnly,
+        };
+        assert!(verify(&uri, &config, NOW).is_err());
+    }
+
+    #[test]
+    fn test_malformed_signature() {
+        let uri = DirectoryUri::parse("directory:///srv/share?exp=1&sig=xyz").unwrap();
+        let result = verify(&uri, &config(SigningMode::Optional), NOW);
+        assert!(
+            matches!(result, Err(QmpoError::PolicyDenied(msg)) if msg == "malformed signature")
+        );
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(decode_hex(&encode_hex(&bytes)), Some(bytes.to_vec()));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
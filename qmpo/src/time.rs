@@ -0,0 +1,915 @@
+//! Time helpers shared by logging, signing and history.
+//!
+//! Timestamps are RFC 3339 with the local UTC offset, computed without an
+//! external chrono dependency. On Unix the offset comes from the TZif file named
+//! by `TZ` (or `/etc/localtime`), including the POSIX TZ rule that covers times
+//! after its last transition; `TZ` may also hold a bare POSIX rule such as
+//! `JST-9`. On Windows it comes from the OS time zone API.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Parse a duration such as `30s`, `15m`, `12h`, `7d` or `2w`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {s}"))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit: {unit}")),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration too large: {s}"))
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 timestamp,
+/// in local time with its UTC offset unless `utc` is set.
+pub fn format_timestamp(secs: u64, utc: bool) -> String {
+    let secs = secs as i64;
+    let offset = if utc { 0 } else { local_offset(secs) };
+    format_rfc3339(secs, offset)
+}
+
+/// Format `secs` shifted by `offset` seconds east of UTC.
+fn format_rfc3339(secs: i64, offset: i32) -> String {
+    // RFC 3339 offsets have minute precision (historic LMT offsets do not)
+    let offset = offset / 60 * 60;
+    let local = secs + i64::from(offset);
+    let (year, month, day) = civil_from_days(local.div_euclid(SECS_PER_DAY));
+    let time_of_day = local.rem_euclid(SECS_PER_DAY);
+
+    let zone = if offset == 0 {
+        "Z".to_string()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let minutes = offset.unsigned_abs() / 60;
+        format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+    };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        (time_of_day % 3600) / 60,
+        time_of_day % 60,
+        zone
+    )
+}
+
+/// Parse an RFC 3339 timestamp as written by [`format_timestamp`] into seconds
+/// since the Unix epoch. `YYYY-MM-DD HH:MM:SS` from older logs is read as UTC.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = s.get(range)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators
+        .iter()
+        .any(|&(i, c)| s.as_bytes().get(i) != Some(&c))
+        || !matches!(s.as_bytes().get(10), Some(b'T' | b' '))
+    {
+        return None;
+    }
+
+    let (month, day) = (number(5..7)?, number(8..10)?);
+    if !(1..=12).contains(&month) || day < 1 {
+        return None;
+    }
+    let days = days_from_civil(number(0..4)?, month as u32, day as u32);
+    let local =
+        days * SECS_PER_DAY + number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+
+    let offset = match s.get(19..)? {
+        "" | "Z" => 0,
+        zone if zone.len() == 6 && zone.as_bytes()[3] == b':' => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            sign * (number(20..22)? * 3600 + number(23..25)? * 60)
+        }
+        _ => return None,
+    };
+    u64::try_from(local - offset).ok()
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date.
+///
+/// Proleptic Gregorian calendar; see Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a civil date to days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Day of the week for days since 1970-01-01 (0 = Sunday).
+fn weekday(days: i64) -> u32 {
+    // 1970-01-01 was a Thursday
+    (days + 4).rem_euclid(7) as u32
+}
+
+/// Local UTC offset in seconds at `secs` since the Unix epoch.
+#[cfg(not(target_os = "windows"))]
+fn local_offset(secs: i64) -> i32 {
+    use std::sync::OnceLock;
+
+    static ZONE: OnceLock<Option<Zone>> = OnceLock::new();
+    ZONE.get_or_init(Zone::load)
+        .as_ref()
+        .map_or(0, |zone| zone.offset_at(secs))
+}
+
+/// Local UTC offset in seconds at `secs` since the Unix epoch.
+#[cfg(target_os = "windows")]
+fn local_offset(secs: i64) -> i32 {
+    use windows_sys::Win32::Foundation::SYSTEMTIME;
+    use windows_sys::Win32::System::Time::SystemTimeToTzSpecificLocalTime;
+
+    let days = secs.div_euclid(SECS_PER_DAY);
+    let time_of_day = secs.rem_euclid(SECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let utc = SYSTEMTIME {
+        wYear: year as u16,
+        wMonth: month as u16,
+        wDayOfWeek: weekday(days) as u16,
+        wDay: day as u16,
+        wHour: (time_of_day / 3600) as u16,
+        wMinute: ((time_of_day % 3600) / 60) as u16,
+        wSecond: (time_of_day % 60) as u16,
+        wMilliseconds: 0,
+    };
+    let mut local = SYSTEMTIME::default();
+
+    // SAFETY: both pointers refer to live SYSTEMTIME values; a null time zone
+    // selects the currently active one.
+    if unsafe { SystemTimeToTzSpecificLocalTime(std::ptr::null(), &utc, &mut local) } == 0 {
+        return 0;
+    }
+
+    let local_secs = days_from_civil(
+        i64::from(local.wYear),
+        u32::from(local.wMonth),
+        u32::from(local.wDay),
+    ) * SECS_PER_DAY
+        + i64::from(local.wHour) * 3600
+        + i64::from(local.wMinute) * 60
+        + i64::from(local.wSecond);
+    (local_secs - secs) as i32
+}
+
+/// A time zone: TZif transitions followed by an optional POSIX TZ rule.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+#[derive(Debug)]
+struct Zone {
+    /// Transition times (seconds since the epoch), ascending.
+    transitions: Vec<i64>,
+    /// UTC offset in effect from each transition on.
+    offsets: Vec<i32>,
+    /// UTC offset before the first transition.
+    initial: i32,
+    /// Rule for times after the last transition.
+    rule: Option<PosixTz>,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl Zone {
+    /// Load the local zone from `TZ` or `/etc/localtime`.
+    fn load() -> Option<Self> {
+        use std::fs;
+
+        let tz = std::env::var("TZ").unwrap_or_default();
+        if tz.is_empty() {
+            return fs::read("/etc/localtime")
+                .ok()
+                .and_then(|data| Self::parse_tzif(&data));
+        }
+
+        let tz = tz.strip_prefix(':').unwrap_or(&tz);
+        let candidates: Vec<std::path::PathBuf> = if tz.starts_with('/') {
+            vec![tz.into()]
+        } else if tz.split('/').any(|part| part == "..") {
+            Vec::new()
+        } else {
+            let tzdir = std::env::var("TZDIR").unwrap_or_else(|_| "/usr/share/zoneinfo".into());
+            vec![
+                std::path::Path::new(&tzdir).join(tz),
+                std::path::Path::new("/usr/lib/zoneinfo").join(tz),
+            ]
+        };
+
+        candidates
+            .iter()
+            .filter_map(|path| fs::read(path).ok())
+            .find_map(|data| Self::parse_tzif(&data))
+            .or_else(|| PosixTz::parse(tz).map(Self::from_rule))
+    }
+
+    fn from_rule(rule: PosixTz) -> Self {
+        Self {
+            transitions: Vec::new(),
+            offsets: Vec::new(),
+            initial: rule.std_offset,
+            rule: Some(rule),
+        }
+    }
+
+    /// Parse TZif data (RFC 8536), preferring the 64-bit block of version 2+ files.
+    fn parse_tzif(data: &[u8]) -> Option<Self> {
+        let header = TzifHeader::parse(data)?;
+        if header.version == 0 {
+            return Self::parse_tzif_block(data, &header, 4).map(|(zone, _)| zone);
+        }
+
+        let v2 = data.get(header.block_len(4) + TzifHeader::LEN..)?;
+        let header = TzifHeader::parse(v2)?;
+        let (mut zone, end) = Self::parse_tzif_block(v2, &header, 8)?;
+
+        // Footer: "\n<POSIX TZ string>\n"
+        zone.rule = v2
+            .get(end..)
+            .and_then(|footer| footer.strip_prefix(b"\n"))
+            .and_then(|footer| footer.split(|&b| b == b'\n').next())
+            .and_then(|tz| std::str::from_utf8(tz).ok())
+            .and_then(PosixTz::parse);
+        Some(zone)
+    }
+
+    /// Parse one data block; returns the zone and the offset just past the block.
+    fn parse_tzif_block(
+        data: &[u8],
+        header: &TzifHeader,
+        time_size: usize,
+    ) -> Option<(Self, usize)> {
+        let mut pos = TzifHeader::LEN;
+        let mut take = |len: usize| {
+            let slice = data.get(pos..pos + len)?;
+            pos += len;
+            Some(slice)
+        };
+
+        let times = take(header.timecnt * time_size)?;
+        let indices = take(header.timecnt)?;
+        let types = take(header.typecnt * 6)?;
+        let utoffs: Vec<i32> = types
+            .chunks_exact(6)
+            .map(|t| i32::from_be_bytes([t[0], t[1], t[2], t[3]]))
+            .collect();
+
+        let transitions = times
+            .chunks_exact(time_size)
+            .map(|t| match time_size {
+                4 => i64::from(i32::from_be_bytes([t[0], t[1], t[2], t[3]])),
+                _ => i64::from_be_bytes([t[0], t[1], t[2], t[3], t[4], t[5], t[6], t[7]]),
+            })
+            .collect();
+        let offsets = indices
+            .iter()
+            .map(|&i| utoffs.get(usize::from(i)).copied())
+            .collect::<Option<Vec<_>>>()?;
+
+        let end = pos
+            + header.charcnt
+            + header.leapcnt * (time_size + 4)
+            + header.isstdcnt
+            + header.isutcnt;
+        let zone = Self {
+            transitions,
+            offsets,
+            initial: *utoffs.first()?,
+            rule: None,
+        };
+        Some((zone, end))
+    }
+
+    fn offset_at(&self, secs: i64) -> i32 {
+        let n = self.transitions.partition_point(|&t| t <= secs);
+        match &self.rule {
+            // Past the last transition (or none at all): the footer rule applies
+            Some(rule) if n == self.transitions.len() => rule.offset_at(secs),
+            _ if n == 0 => self.initial,
+            _ => self.offsets[n - 1],
+        }
+    }
+}
+
+/// The counts in a TZif header.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+struct TzifHeader {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl TzifHeader {
+    const LEN: usize = 44;
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let version = match *data.get(4)? {
+            0 => 0,
+            v @ b'2'..=b'9' => v - b'0',
+            _ => return None,
+        };
+        let count = |i: usize| -> Option<usize> {
+            let bytes = data.get(20 + i * 4..24 + i * 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+        };
+        Some(Self {
+            version,
+            isutcnt: count(0)?,
+            isstdcnt: count(1)?,
+            leapcnt: count(2)?,
+            timecnt: count(3)?,
+            typecnt: count(4)?,
+            charcnt: count(5)?,
+        })
+    }
+
+    /// Length of the data block following this header.
+    fn block_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// A POSIX TZ rule such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+struct PosixTz {
+    /// Standard time offset, in seconds east of UTC.
+    std_offset: i32,
+    dst: Option<DstRule>,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+struct DstRule {
+    /// Daylight saving time offset, in seconds east of UTC.
+    offset: i32,
+    /// Start of DST, in local standard time.
+    start: Transition,
+    /// End of DST, in local daylight time.
+    end: Transition,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+struct Transition {
+    date: TransitionDate,
+    /// Seconds after local midnight (may be negative or exceed a day).
+    time: i32,
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+enum TransitionDate {
+    /// `Jn`: day 1-365, never counting February 29.
+    Julian(u32),
+    /// `n`: zero-based day 0-365, counting February 29.
+    ZeroBased(u32),
+    /// `Mm.w.d`: weekday `d` (0 = Sunday) of week `w` (5 = last) of month `m`.
+    MonthWeekDay { month: u32, week: u32, weekday: u32 },
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl PosixTz {
+    fn parse(s: &str) -> Option<Self> {
+        let mut p = Cursor(s.as_bytes());
+
+        p.name()?;
+        // POSIX offsets count hours *west* of UTC
+        let std_offset = -p.offset()?;
+        if p.is_empty() {
+            return Some(Self {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        p.name()?;
+        let offset = match p.peek() {
+            Some(b',') | None => std_offset + 3600,
+            _ => -p.offset()?,
+        };
+
+        let (start, end) = if p.is_empty() {
+            // No rule given: use the current US rule, as glibc does
+            (
+                Transition::month_week_day(3, 2, 0),
+                Transition::month_week_day(11, 1, 0),
+            )
+        } else {
+            p.expect(b',')?;
+            let start = p.transition()?;
+            p.expect(b',')?;
+            let end = p.transition()?;
+            (start, end)
+        };
+
+        if !p.is_empty() {
+            return None;
+        }
+        Some(Self {
+            std_offset,
+            dst: Some(DstRule { offset, start, end }),
+        })
+    }
+
+    fn offset_at(&self, secs: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+
+        let year = civil_from_days((secs + i64::from(self.std_offset)).div_euclid(SECS_PER_DAY)).0;
+        let start = dst.start.local_secs(year) - i64::from(self.std_offset);
+        let end = dst.end.local_secs(year) - i64::from(dst.offset);
+
+        let in_dst = if start < end {
+            // Northern hemisphere: DST within the year
+            (start..end).contains(&secs)
+        } else {
+            // Southern hemisphere: DST spans the new year
+            !(end..start).contains(&secs)
+        };
+        if in_dst { dst.offset } else { self.std_offset }
+    }
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl Transition {
+    fn month_week_day(month: u32, week: u32, weekday: u32) -> Self {
+        Self {
+            date: TransitionDate::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            },
+            time: 2 * 3600,
+        }
+    }
+
+    /// Local time of the transition in `year`, as seconds since the epoch.
+    fn local_secs(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let day = match self.date {
+            TransitionDate::Julian(n) => {
+                let leap_shift = i64::from(is_leap_year(year) && n >= 60);
+                jan1 + i64::from(n) - 1 + leap_shift
+            }
+            TransitionDate::ZeroBased(n) => jan1 + i64::from(n),
+            TransitionDate::MonthWeekDay {
+                month,
+                week,
+                weekday: wd,
+            } => {
+                let first = days_from_civil(year, month, 1);
+                let first_match = first + i64::from((wd + 7 - weekday(first)) % 7);
+                let mut day = first_match + i64::from(week - 1) * 7;
+                if day >= first + i64::from(days_in_month(year, month)) {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * SECS_PER_DAY + i64::from(self.time)
+    }
+}
+
+/// Minimal byte cursor for parsing POSIX TZ strings.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+struct Cursor<'a>(&'a [u8]);
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+impl Cursor<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        (self.peek()? == b).then(|| self.0 = &self.0[1..])
+    }
+
+    /// Zone abbreviation: `<...>` or at least three letters.
+    fn name(&mut self) -> Option<()> {
+        if self.peek()? == b'<' {
+            let end = self.0.iter().position(|&b| b == b'>')?;
+            self.0 = &self.0[end + 1..];
+            return Some(());
+        }
+        let len = self
+            .0
+            .iter()
+            .take_while(|b| b.is_ascii_alphabetic())
+            .count();
+        if len < 3 {
+            return None;
+        }
+        self.0 = &self.0[len..];
+        Some(())
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let len = self.0.iter().take_while(|b| b.is_ascii_digit()).count();
+        let n = std::str::from_utf8(&self.0[..len]).ok()?.parse().ok()?;
+        self.0 = &self.0[len..];
+        Some(n)
+    }
+
+    /// `[+-]hh[:mm[:ss]]`, in seconds. Hours go up to 167 as in POSIX transition
+    /// times; anything larger is rejected rather than overflowing.
+    fn offset(&mut self) -> Option<i32> {
+        let sign = match self.peek()? {
+            b'-' => {
+                self.0 = &self.0[1..];
+                -1
+            }
+            b'+' => {
+                self.0 = &self.0[1..];
+                1
+            }
+            _ => 1,
+        };
+        let mut secs = self.number().filter(|h| *h <= 167)?.checked_mul(3600)?;
+        if self.peek() == Some(b':') {
+            self.0 = &self.0[1..];
+            secs = secs.checked_add(self.number().filter(|m| *m <= 59)? * 60)?;
+            if self.peek() == Some(b':') {
+                self.0 = &self.0[1..];
+                secs = secs.checked_add(self.number().filter(|s| *s <= 59)?)?;
+            }
+        }
+        Some(sign * i32::try_from(secs).ok()?)
+    }
+
+    /// `Jn`, `n` or `Mm.w.d`, optionally followed by `/time`.
+    fn transition(&mut self) -> Option<Transition> {
+        let date = match self.peek()? {
+            b'J' => {
+                self.0 = &self.0[1..];
+                TransitionDate::Julian(self.number().filter(|n| (1..=365).contains(n))?)
+            }
+            b'M' => {
+                self.0 = &self.0[1..];
+                let month = self.number().filter(|m| (1..=12).contains(m))?;
+                self.expect(b'.')?;
+                let week = self.number().filter(|w| (1..=5).contains(w))?;
+                self.expect(b'.')?;
+                let weekday = self.number().filter(|d| *d <= 6)?;
+                TransitionDate::MonthWeekDay {
+                    month,
+                    week,
+                    weekday,
+                }
+            }
+            _ => TransitionDate::ZeroBased(self.number().filter(|n| *n <= 365)?),
+        };
+        let time = if self.peek() == Some(b'/') {
+            self.0 = &self.0[1..];
+            self.offset()?
+        } else {
+            2 * 3600
+        };
+        Some(Transition { date, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since the epoch for a UTC civil time.
+    fn utc(year: i64, month: u32, day: u32, hour: i64, minute: i64, second: i64) -> i64 {
+        days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(43_200)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(604_800)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(1_209_600)));
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_timestamp(0, true), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(1_700_000_000, true),
+            "2023-11-14T22:13:20Z"
+        );
+    }
+
+    #[test]
+    fn test_format_with_offset() {
+        assert_eq!(format_rfc3339(0, 9 * 3600), "1970-01-01T09:00:00+09:00");
+        assert_eq!(
+            format_rfc3339(0, -(3 * 3600 + 1800)),
+            "1969-12-31T20:30:00-03:30"
+        );
+        // 2023-12-31T15:00:00Z is already New Year in Tokyo
+        assert_eq!(
+            format_rfc3339(utc(2023, 12, 31, 15, 0, 0), 9 * 3600),
+            "2024-01-01T00:00:00+09:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_timestamp("2023-11-15T07:13:20+09:00"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-14T17:43:20-04:30"),
+            Some(1_700_000_000)
+        );
+        assert_eq!(parse_timestamp("2023-11-14 22:13:20"), Some(1_700_000_000));
+        assert_eq!(parse_timestamp("2023-13-14T22:13:20Z"), None);
+        assert_eq!(parse_timestamp("2023-11-14T22:13:20+0900"), None);
+        assert_eq!(parse_timestamp("not a timestamp"), None);
+    }
+
+    #[test]
+    fn test_format_parse_roundtrip() {
+        for secs in [0, 951_782_400, 1_700_000_000] {
+            for offset in [0, 9 * 3600, -(5 * 3600)] {
+                assert_eq!(
+                    parse_timestamp(&format_rfc3339(secs, offset)),
+                    Some(secs as u64)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_leap_years() {
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+        assert!(!is_leap_year(2023));
+
+        assert_eq!(format_rfc3339(951_782_400, 0), "2000-02-29T00:00:00Z");
+        assert_eq!(
+            format_rfc3339(utc(2024, 2, 28, 23, 59, 59) + 1, 0),
+            "2024-02-29T00:00:00Z"
+        );
+        assert_eq!(
+            format_rfc3339(utc(2024, 2, 29, 23, 59, 59) + 1, 0),
+            "2024-03-01T00:00:00Z"
+        );
+        assert_eq!(
+            format_rfc3339(utc(2100, 2, 28, 23, 59, 59) + 1, 0),
+            "2100-03-01T00:00:00Z"
+        );
+        assert_eq!(
+            format_rfc3339(utc(2024, 12, 31, 23, 59, 59) + 1, 0),
+            "2025-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_civil_roundtrip() {
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert!((1..=12).contains(&month));
+            assert!((1..=days_in_month(year, month)).contains(&day));
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(weekday(0), 4); // 1970-01-01 was a Thursday
+        assert_eq!(weekday(days_from_civil(2024, 3, 10)), 0); // Sunday
+    }
+
+    #[test]
+    fn test_posix_fixed_offset() {
+        let tz = PosixTz::parse("JST-9").unwrap();
+        assert_eq!(tz.dst, None);
+        assert_eq!(tz.offset_at(0), 9 * 3600);
+
+        let tz = PosixTz::parse("<+0530>-5:30").unwrap();
+        assert_eq!(tz.offset_at(0), 5 * 3600 + 1800);
+    }
+
+    #[test]
+    fn test_posix_us_dst_boundaries() {
+        let tz = PosixTz::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // 2024-03-10 02:00 EST = 07:00Z
+        assert_eq!(tz.offset_at(utc(2024, 3, 10, 6, 59, 59)), -5 * 3600);
+        assert_eq!(tz.offset_at(utc(2024, 3, 10, 7, 0, 0)), -4 * 3600);
+        // 2024-11-03 02:00 EDT = 06:00Z
+        assert_eq!(tz.offset_at(utc(2024, 11, 3, 5, 59, 59)), -4 * 3600);
+        assert_eq!(tz.offset_at(utc(2024, 11, 3, 6, 0, 0)), -5 * 3600);
+    }
+
+    #[test]
+    fn test_posix_default_rule() {
+        assert_eq!(
+            PosixTz::parse("EST5EDT"),
+            PosixTz::parse("EST5EDT,M3.2.0,M11.1.0")
+        );
+    }
+
+    #[test]
+    fn test_posix_eu_dst_boundaries() {
+        let tz = PosixTz::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // Last Sunday of March 2024 is the 31st; 02:00 CET = 01:00Z
+        assert_eq!(tz.offset_at(utc(2024, 3, 31, 0, 59, 59)), 3600);
+        assert_eq!(tz.offset_at(utc(2024, 3, 31, 1, 0, 0)), 7200);
+        // Last Sunday of October 2024 is the 27th; 03:00 CEST = 01:00Z
+        assert_eq!(tz.offset_at(utc(2024, 10, 27, 0, 59, 59)), 7200);
+        assert_eq!(tz.offset_at(utc(2024, 10, 27, 1, 0, 0)), 3600);
+    }
+
+    #[test]
+    fn test_posix_southern_hemisphere() {
+        let tz = PosixTz::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        // 2024-04-07 03:00 AEDT = 2024-04-06 16:00Z
+        assert_eq!(tz.offset_at(utc(2024, 4, 6, 15, 59, 59)), 11 * 3600);
+        assert_eq!(tz.offset_at(utc(2024, 4, 6, 16, 0, 0)), 10 * 3600);
+        // 2024-10-06 02:00 AEST = 2024-10-05 16:00Z
+        assert_eq!(tz.offset_at(utc(2024, 10, 5, 15, 59, 59)), 10 * 3600);
+        assert_eq!(tz.offset_at(utc(2024, 10, 5, 16, 0, 0)), 11 * 3600);
+        // Midsummer and midwinter
+        assert_eq!(tz.offset_at(utc(2024, 1, 1, 0, 0, 0)), 11 * 3600);
+        assert_eq!(tz.offset_at(utc(2024, 7, 1, 0, 0, 0)), 10 * 3600);
+    }
+
+    #[test]
+    fn test_posix_julian_dates() {
+        let tz = PosixTz::parse("XST0XDT,J60/0,J300/0").unwrap();
+        // J60 is March 1 even in leap years
+        assert_eq!(tz.offset_at(utc(2024, 2, 29, 23, 59, 59)), 0);
+        assert_eq!(tz.offset_at(utc(2024, 3, 1, 0, 0, 0)), 3600);
+
+        let tz = PosixTz::parse("XST0XDT,59/0,300/0").unwrap();
+        // Zero-based day 59 is February 29 in leap years
+        assert_eq!(tz.offset_at(utc(2024, 2, 28, 23, 59, 59)), 0);
+        assert_eq!(tz.offset_at(utc(2024, 2, 29, 0, 0, 0)), 3600);
+    }
+
+    #[test]
+    fn test_posix_invalid() {
+        assert_eq!(PosixTz::parse(""), None);
+        assert_eq!(PosixTz::parse("X5"), None);
+        assert_eq!(PosixTz::parse("EST5EDT,M13.1.0,M11.1.0"), None);
+        assert_eq!(PosixTz::parse("EST5EDT,M3.2.0"), None);
+
+        // Out-of-range offsets and times are rejected rather than overflowing
+        assert_eq!(PosixTz::parse("XXX9999999"), None);
+        assert_eq!(PosixTz::parse("XXX99999999999"), None);
+        assert_eq!(PosixTz::parse("XXX168"), None);
+        assert_eq!(PosixTz::parse("XXX5:60"), None);
+        assert_eq!(PosixTz::parse("EST5EDT,M3.2.0/9999999,M11.1.0"), None);
+        assert!(PosixTz::parse("XXX167").is_some());
+    }
+
+    /// Build a version 2 TZif file with the given transitions and footer.
+    fn tzif(transitions: &[(i64, u8)], utoffs: &[i32], footer: &str) -> Vec<u8> {
+        let block = |time_size: usize| {
+            let mut out = b"TZif2".to_vec();
+            out.extend([0; 15]);
+            let charcnt = 4u32;
+            for count in [
+                0,
+                0,
+                0,
+                transitions.len() as u32,
+                utoffs.len() as u32,
+                charcnt,
+            ] {
+                out.extend(count.to_be_bytes());
+            }
+            for (t, _) in transitions {
+                match time_size {
+                    4 => out.extend((*t as i32).to_be_bytes()),
+                    _ => out.extend(t.to_be_bytes()),
+                }
+            }
+            out.extend(transitions.iter().map(|(_, i)| i));
+            for utoff in utoffs {
+                out.extend(utoff.to_be_bytes());
+                out.extend([0, 0]);
+            }
+            out.extend(b"XXX\0");
+            out
+        };
+        let mut data = block(4);
+        data.extend(block(8));
+        data.extend(format!("\n{footer}\n").as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_tzif_transitions_and_footer() {
+        // Local mean time, then standard time from 1900, then the footer rule
+        let data = tzif(
+            &[(-2_208_988_800, 1), (utc(2000, 1, 1, 0, 0, 0), 2)],
+            &[3600 + 60, 3600, 7200],
+            "CET-1CEST,M3.5.0,M10.5.0/3",
+        );
+        let zone = Zone::parse_tzif(&data).unwrap();
+
+        assert_eq!(zone.offset_at(-3_000_000_000), 3660);
+        assert_eq!(zone.offset_at(0), 3600);
+        assert_eq!(zone.offset_at(utc(1999, 12, 31, 23, 59, 59)), 3600);
+        // After the last transition, the footer rule applies
+        assert_eq!(zone.offset_at(utc(2024, 1, 15, 0, 0, 0)), 3600);
+        assert_eq!(zone.offset_at(utc(2024, 7, 15, 0, 0, 0)), 7200);
+    }
+
+    #[test]
+    fn test_tzif_without_transitions() {
+        let data = tzif(&[], &[9 * 3600], "JST-9");
+        let zone = Zone::parse_tzif(&data).unwrap();
+        assert_eq!(zone.offset_at(1_700_000_000), 9 * 3600);
+    }
+
+    #[test]
+    fn test_tzif_rejects_garbage() {
+        assert!(Zone::parse_tzif(b"not a tzif file at all").is_none());
+        assert!(Zone::parse_tzif(&tzif(&[(0, 5)], &[0], "UTC0")).is_none());
+    }
+}
@@ -2,18 +2,113 @@
 //!
 //! This module provides [`DirectoryUri`] for parsing and converting URIs to filesystem paths.
 
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::string::FromUtf8Error;
 
-use percent_encoding::percent_decode_str;
+use encoding_rs::Encoding;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, percent_encode};
+use serde::{Deserialize, Deserializer};
 use url::Url;
 
 use super::error::{QmpoError, Result};
+use super::placeholder::Placeholder;
+use super::webdav::WebDav;
+use super::winpath::WinPath;
 
 /// The URI scheme identifier.
 const SCHEME: &str = "directory";
 /// The full scheme prefix including the separator.
 const SCHEME_PREFIX: &str = "directory://";
 
+/// Characters percent-encoded in path segments when building a URI.
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Settings for the `[uri]` config section.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UriConfig {
+    /// Encodings tried in order when a percent-decoded path is not valid UTF-8,
+    /// e.g. `["cp932", "latin1"]`.
+    pub fallback_encodings: Vec<FallbackEncoding>,
+    /// Treat this machine's own host name as a local authority, like `localhost`.
+    pub local_hostname: bool,
+}
+
+impl UriConfig {
+    /// The options [`DirectoryUri::parse_with`] takes from this section.
+    ///
+    /// `hostname` is this machine's host name, consulted only if
+    /// `local_hostname` is set.
+    pub fn parse_options<'a>(&'a self, hostname: Option<&'a str>) -> ParseOptions<'a> {
+        ParseOptions {
+            fallbacks: &self.fallback_encodings,
+            hostname: hostname.filter(|_| self.local_hostname),
+        }
+    }
+}
+
+/// Options for [`DirectoryUri::parse_with`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseOptions<'a> {
+    /// Encodings tried in order when a path is not valid UTF-8.
+    pub fallbacks: &'a [FallbackEncoding],
+    /// This machine's host name, treated as a local authority.
+    pub hostname: Option<&'a str>,
+}
+
+/// A legacy encoding for percent-decoded paths, given by its WHATWG label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FallbackEncoding(&'static Encoding);
+
+impl FallbackEncoding {
+    /// Look up an encoding by label. `cp932` is accepted as Shift_JIS, which
+    /// decodes Windows' CP932 extensions as well.
+    ///
+    /// Only ASCII-compatible encodings are accepted, since `/` and `%` must keep
+    /// their meaning.
+    pub fn for_label(label: &str) -> Option<Self> {
+        let label = match label.trim().to_ascii_lowercase().as_str() {
+            "cp932" | "ms-932" => "shift_jis".to_string(),
+            other => other.to_string(),
+        };
+        Encoding::for_label(label.as_bytes())
+            .filter(|encoding| encoding.is_ascii_compatible() && *encoding != encoding_rs::UTF_8)
+            .map(Self)
+    }
+
+    /// The encoding's canonical name, e.g. `Shift_JIS`.
+    pub fn name(self) -> &'static str {
+        self.0.name()
+    }
+
+    /// Decode `bytes`, failing on any malformed or unmapped sequence.
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        self.0
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|decoded| decoded.into_owned())
+    }
+}
+
+impl<'de> Deserialize<'de> for FallbackEncoding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        Self::for_label(&label)
+            .ok_or_else(|| serde::de::Error::custom(format!("unsupported encoding: {label}")))
+    }
+}
+
 /// A parsed `directory://` URI that holds a filesystem path.
 ///
 /// This struct is the main entry point for URI parsing. It handles:
@@ -21,6 +116,38 @@ const SCHEME_PREFIX: &str = "directory://";
 /// - Windows local paths: `directory://C:/path/to/dir`
 /// - Windows UNC paths: `directory://server/share/path`
 ///
+/// # Authority
+///
+/// The authority (the part between `//` and the next `/`) decides how the rest
+/// is read, following RFC 8089 for `file:` URIs:
+///
+/// | Authority | Meaning | Example |
+/// |-----------|---------|---------|
+/// | empty | local path | `directory:///home/me` → `/home/me` |
+/// | `localhost` (any case) | local path | `directory://localhost/home/me` → `/home/me` |
+/// | this machine's host name, if configured | local path | `directory://mybox/home/me` → `/home/me` |
+/// | drive letter | Windows local path | `directory://C:/Users` → `C:\Users` |
+/// | `known` or `env` | placeholder | `directory://known/Downloads` |
+/// | any other host | UNC path | `directory://server/share` → `\\server\share` |
+///
+/// A local path that starts with a drive letter, as in
+/// `directory://localhost/C:/Users` or `directory:///C:/Users`, is a Windows
+/// local path. The host name is only matched when passed in [`ParseOptions`];
+/// a name other than `localhost` never matches by default, since a server may
+/// share this machine's name in links written elsewhere.
+///
+/// Placeholders (`directory:///~/...`, `directory://known/<Name>/...` and
+/// `directory://env/<NAME>/...`) are kept unexpanded in the path and reported
+/// by [`DirectoryUri::placeholder`]; they are expanded when the link is opened.
+///
+/// An optional `?exp=<unix time>&sig=<hex>` query carries a link signature
+/// (see [`Signature`]). The query is not part of the path.
+///
+/// Percent-escapes are decoded as UTF-8. [`DirectoryUri::parse_with`]
+/// also accepts paths in legacy encodings such as CP932. On Unix, a path whose
+/// bytes are valid in no accepted encoding is kept as raw bytes, since Unix file
+/// names need not be text; [`DirectoryUri::target`] encodes it back unchanged.
+///
 /// # Example
 ///
 /// ```
@@ -33,6 +160,20 @@ const SCHEME_PREFIX: &str = "directory://";
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DirectoryUri {
     path: PathBuf,
+    signature: Option<Signature>,
+    /// The fallback encoding the path was decoded with, if it was not UTF-8.
+    encoding: Option<FallbackEncoding>,
+    /// The per-user location the path is relative to, if any.
+    placeholder: Option<Placeholder>,
+}
+
+/// The `sig` and `exp` query parameters of a signed link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Hex-encoded HMAC-SHA256 over the target and expiry.
+    pub sig: String,
+    /// Expiry as seconds since the Unix epoch.
+    pub exp: u64,
 }
 
 impl DirectoryUri {
@@ -54,7 +195,8 @@ impl DirectoryUri {
     ///
     /// - `directory://X:/...` (single letter followed by colon) → Windows local path
     /// - `directory://server/...` (no drive letter pattern) → UNC path
-    /// - `directory:///...` (triple slash) → Unix absolute path
+    /// - `directory:///...` (triple slash) or `directory://localhost/...` → Unix
+    ///   absolute path, or a Windows local path if a drive letter follows
     ///
     /// # Errors
     ///
@@ -62,7 +204,9 @@ impl DirectoryUri {
     /// - The URI scheme is not `directory`
     /// - The URI format is invalid
     /// - The path is empty
-    /// - Percent-encoding contains invalid UTF-8
+    /// - Percent-encoding contains invalid UTF-8 in a Windows path, or in any
+    ///   path on platforms other than Unix
+    /// - Only one of `sig` and `exp` is present, or `exp` is not a number
     ///
     /// # Example
     ///
@@ -79,22 +223,110 @@ impl DirectoryUri {
     /// # Ok::<(), qmpo_core::QmpoError>(())
     /// ```
     pub fn parse(uri_str: &str) -> Result<Self> {
+        Self::parse_with(uri_str, &ParseOptions::default())
+    }
+
+    /// Parse a directory URI with extra options.
+    ///
+    /// A path that is not valid UTF-8 is decoded with the first of
+    /// `options.fallbacks` that decodes it without errors, and an authority
+    /// equal to `options.hostname` is local (see [Authority](#authority)).
+    ///
+    /// # Errors
+    ///
+    /// As [`DirectoryUri::parse`]; invalid UTF-8 is an error only when no
+    /// fallback encoding decodes the path.
+    pub fn parse_with(uri_str: &str, options: &ParseOptions<'_>) -> Result<Self> {
         let url = Url::parse(uri_str)?;
 
         if url.scheme() != SCHEME {
             return Err(QmpoError::InvalidScheme(url.scheme().to_string()));
         }
 
-        let path = Self::extract_path(uri_str)?;
+        // The query (if any) carries parameters, not path characters
+        let target = uri_str
+            .split_once('?')
+            .map_or(uri_str, |(target, _)| target);
+        let (path, encoding) = Self::extract_path(target, options)?;
 
         if path.as_os_str().is_empty() {
             return Err(QmpoError::EmptyPath);
         }
 
-        Ok(Self { path })
+        let signature = Self::extract_signature(&url)?;
+        let placeholder = Placeholder::from_path(&path)?;
+
+        Ok(Self {
+            path,
+            signature,
+            encoding,
+            placeholder,
+        })
+    }
+
+    /// Build a URI for an absolute filesystem path.
+    ///
+    /// Accepts Unix absolute paths, Windows drive paths and UNC paths
+    /// regardless of the current platform.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`QmpoError::InvalidUri`] if the path is not absolute, or is not
+    /// valid UTF-8 and not a Unix path.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let Some(s) = path.to_str() else {
+            if cfg!(unix) && path.as_os_str().as_encoded_bytes().starts_with(b"/") {
+                return Ok(Self {
+                    path: path.to_path_buf(),
+                    signature: None,
+                    encoding: None,
+                    placeholder: None,
+                });
+            }
+            return Err(QmpoError::InvalidUri("path is not valid UTF-8".to_string()));
+        };
+
+        if !(s.starts_with('/') || s.starts_with("\\\\") || is_windows_drive_letter(s)) {
+            return Err(QmpoError::InvalidUri(format!("path is not absolute: {s}")));
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            signature: None,
+            encoding: None,
+            placeholder: None,
+        })
+    }
+
+    fn extract_signature(url: &Url) -> Result<Option<Signature>> {
+        let mut sig = None;
+        let mut exp = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "sig" => sig = Some(value.into_owned()),
+                "exp" => exp = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        match (sig, exp) {
+            (None, None) => Ok(None),
+            (Some(sig), Some(exp)) => {
+                let exp = exp
+                    .parse()
+                    .map_err(|_| QmpoError::InvalidUri(format!("invalid exp: {exp}")))?;
+                Ok(Some(Signature { sig, exp }))
+            }
+            _ => Err(QmpoError::InvalidUri(
+                "sig and exp must be given together".to_string(),
+            )),
+        }
     }
 
-    fn extract_path(original_uri: &str) -> Result<PathBuf> {
+    fn extract_path(
+        original_uri: &str,
+        options: &ParseOptions<'_>,
+    ) -> Result<(PathBuf, Option<FallbackEncoding>)> {
         let after_scheme = original_uri
             .strip_prefix(SCHEME_PREFIX)
             .ok_or_else(|| QmpoError::InvalidUri("missing scheme prefix".to_string()))?;
@@ -103,19 +335,39 @@ impl DirectoryUri {
             return Err(QmpoError::EmptyPath);
         }
 
-        let decoded = decode_percent_encoding(after_scheme)?;
+        // directory://localhost/home/me is read as directory:///home/me
+        let (authority, rest) = after_scheme
+            .find('/')
+            .map_or((after_scheme, "/"), |i| after_scheme.split_at(i));
+        let after_scheme = if is_local_authority(authority, options.hostname) {
+            rest
+        } else {
+            after_scheme
+        };
 
+        match decode_percent_encoding(after_scheme, options.fallbacks) {
+            Ok((decoded, encoding)) => {
+                Ok((Self::path_from_decoded(after_scheme, decoded), encoding))
+            }
+            Err(e) => Ok((raw_unix_path(after_scheme, e)?, None)),
+        }
+    }
+
+    /// Convert the decoded part after the scheme into a path.
+    fn path_from_decoded(after_scheme: &str, decoded: String) -> PathBuf {
         // Unix absolute path: directory:///home/tagawa -> /home/tagawa
         if after_scheme.starts_with('/') {
-            // Also check for Windows drive letter without colon after the leading slash
-            // e.g., directory:///C/Windows -> /C/Windows -> C:/Windows
+            // Also check for Windows drive letter after the leading slash, with or
+            // without colon: directory:///C/Windows -> /C/Windows -> C:/Windows
             let path_after_slash = decoded.strip_prefix('/').unwrap_or(&decoded);
-            if is_windows_drive_letter_without_colon(path_after_slash) {
+            if is_windows_drive_root(path_after_slash)
+                || is_windows_drive_letter_without_colon(path_after_slash)
+            {
                 let fixed = fix_windows_drive_letter(path_after_slash);
                 let windows_path = fixed.replace('/', "\\");
-                return Ok(PathBuf::from(windows_path));
+                return PathBuf::from(windows_path);
             }
-            return Ok(PathBuf::from(decoded));
+            return PathBuf::from(decoded);
         }
 
         // Fix Windows drive letter without colon (e.g., C/Windows -> C:/Windows)
@@ -125,12 +377,12 @@ impl DirectoryUri {
         // Windows drive letter pattern (e.g., C:/)
         if is_windows_drive_letter(&decoded) {
             let windows_path = decoded.replace('/', "\\");
-            return Ok(PathBuf::from(windows_path));
+            return PathBuf::from(windows_path);
         }
 
         // UNC path: directory://server/share -> \\server\share
         let unc_path = format!("\\\\{}", decoded.replace('/', "\\"));
-        Ok(PathBuf::from(unc_path))
+        PathBuf::from(unc_path)
     }
 
     /// Returns a reference to the filesystem path.
@@ -142,14 +394,168 @@ impl DirectoryUri {
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// Returns the link signature, if the URI carries one.
+    #[inline]
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    /// Returns the WebDAV folder a UNC path such as
+    /// `\\files.example.com@SSL@443\DavWWWRoot\team` names.
+    pub fn webdav(&self) -> Option<WebDav> {
+        WebDav::from_unc(&WinPath::parse(self.path.to_str()?)?)
+    }
+
+    /// Returns the placeholder the path starts with, such as `~` in
+    /// `directory:///~/notes`. [`DirectoryUri::path`] is then the unexpanded
+    /// path (`/~/notes`).
+    #[inline]
+    pub fn placeholder(&self) -> Option<&Placeholder> {
+        self.placeholder.as_ref()
+    }
+
+    /// Returns the fallback encoding the path was decoded with, or `None` for UTF-8.
+    #[inline]
+    pub fn encoding(&self) -> Option<FallbackEncoding> {
+        self.encoding
+    }
+
+    /// Attach a signature, replacing any existing one.
+    pub fn with_signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Returns the normalized target: the URI without its query.
+    ///
+    /// Equivalent spellings (e.g. `C/Windows` and `C:/Windows`) produce the same target.
+    /// Unix paths that are not valid UTF-8 are encoded byte for byte.
+    pub fn target(&self) -> String {
+        let Some(s) = self.path.to_str() else {
+            // Only Unix paths hold names that are not UTF-8 (see `from_path`)
+            let bytes = self.path.as_os_str().as_encoded_bytes();
+            let rest = bytes.strip_prefix(b"/").unwrap_or(bytes);
+            return format!(
+                "{SCHEME_PREFIX}/{}",
+                encode_segments(rest.split(|&b| b == b'/'))
+            );
+        };
+
+        if let Some(unc) = s.strip_prefix("\\\\") {
+            return format!(
+                "{SCHEME_PREFIX}{}",
+                encode_segments(unc.split('\\').map(str::as_bytes))
+            );
+        }
+        if is_windows_drive_letter(s) {
+            let (drive, rest) = s.split_at(2);
+            let rest = rest.strip_prefix('\\').unwrap_or(rest);
+            return format!(
+                "{SCHEME_PREFIX}{drive}/{}",
+                encode_segments(rest.split('\\').map(str::as_bytes))
+            );
+        }
+        let rest = s.strip_prefix('/').unwrap_or(s);
+        format!(
+            "{SCHEME_PREFIX}/{}",
+            encode_segments(rest.split('/').map(str::as_bytes))
+        )
+    }
+}
+
+impl fmt::Display for DirectoryUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.target())?;
+        if let Some(signature) = &self.signature {
+            write!(f, "?exp={}&sig={}", signature.exp, signature.sig)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode path segments and join them with `/`.
+fn encode_segments<'a>(segments: impl Iterator<Item = &'a [u8]>) -> String {
+    segments
+        .map(|segment| percent_encode(segment, PATH_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decode a percent-encoded string as UTF-8, or else with the first fallback
+/// encoding that decodes it without errors.
+///
+/// On failure, the error holds the decoded bytes.
+fn decode_percent_encoding(
+    s: &str,
+    fallbacks: &[FallbackEncoding],
+) -> std::result::Result<(String, Option<FallbackEncoding>), FromUtf8Error> {
+    let bytes: Vec<u8> = percent_decode_str(s).collect();
+    match String::from_utf8(bytes) {
+        Ok(decoded) => Ok((decoded, None)),
+        Err(e) => fallbacks
+            .iter()
+            .find_map(|&fallback| Some((fallback.decode(e.as_bytes())?, Some(fallback))))
+            .ok_or(e),
+    }
+}
+
+/// Keep the decoded bytes of a Unix path that is not valid in any accepted encoding.
+///
+/// Windows paths are Unicode, so for them (and off Unix) this is an error.
+fn raw_unix_path(after_scheme: &str, e: FromUtf8Error) -> Result<PathBuf> {
+    let invalid =
+        |e: FromUtf8Error| QmpoError::InvalidUri(format!("invalid UTF-8: {}", e.utf8_error()));
+    let after_slash = e.as_bytes().strip_prefix(b"/");
+    let is_drive = after_slash.is_some_and(|rest| {
+        matches!(rest, [letter, b'/', ..] | [letter, b':', b'/', ..] if letter.is_ascii_alphabetic())
+    });
+    if !after_scheme.starts_with('/') || is_drive {
+        return Err(invalid(e));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(PathBuf::from(std::ffi::OsString::from_vec(e.into_bytes())))
+    }
+    #[cfg(not(unix))]
+    Err(invalid(e))
+}
+
+/// Check if an authority names this machine: `localhost`, or `hostname` or its
+/// first label.
+fn is_local_authority(authority: &str, hostname: Option<&str>) -> bool {
+    if authority.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    hostname.is_some_and(|hostname| {
+        let short = hostname.split('.').next().unwrap_or(hostname);
+        !authority.is_empty()
+            && (authority.eq_ignore_ascii_case(hostname) || authority.eq_ignore_ascii_case(short))
+    })
+}
+
+/// This machine's host name, if it can be determined.
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its length; the name is NUL-terminated
+    // unless truncated, which the last byte guards against.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0)?;
+    String::from_utf8(buf[..len].to_vec())
+        .ok()
+        .filter(|name| !name.is_empty())
 }
 
-/// Decode percent-encoded string to UTF-8.
-fn decode_percent_encoding(s: &str) -> Result<String> {
-    percent_decode_str(s)
-        .decode_utf8()
-        .map(|cow| cow.into_owned())
-        .map_err(|e| QmpoError::InvalidUri(format!("invalid UTF-8: {e}")))
+/// This machine's host name, if it can be determined.
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
 }
 
 /// Check if a string starts with a Windows drive letter pattern (e.g., "C:").
@@ -161,6 +567,12 @@ fn is_windows_drive_letter(s: &str) -> bool {
     )
 }
 
+/// Check if a string is a drive letter with colon, alone or followed by `/`
+/// (e.g., "C:" or "C:/Users"), unlike a Unix name such as "a:b".
+fn is_windows_drive_root(s: &str) -> bool {
+    is_windows_drive_letter(s) && matches!(s.as_bytes().get(2), None | Some(b'/'))
+}
+
 /// Check if a string starts with a Windows drive letter without colon (e.g., "C/").
 /// Some browsers convert "C:/" to "C/" when handling file:// URLs.
 fn is_windows_drive_letter_without_colon(s: &str) -> bool {
@@ -192,6 +604,16 @@ fn fix_windows_drive_letter(s: &str) -> String {
 mod tests {
     use super::*;
 
+    fn parse_with_fallbacks(uri: &str, fallbacks: &[FallbackEncoding]) -> Result<DirectoryUri> {
+        DirectoryUri::parse_with(
+            uri,
+            &ParseOptions {
+                fallbacks,
+                hostname: None,
+            },
+        )
+    }
+
     // Basic path tests
     #[test]
     fn test_unix_path() {
@@ -262,6 +684,69 @@ mod tests {
         assert_eq!(uri.path(), &PathBuf::from("\\\\server\\share"));
     }
 
+    // RFC 8089 authority forms
+    #[test]
+    fn test_localhost_is_local() {
+        for uri in [
+            "directory://localhost/home/me",
+            "directory://LocalHost/home/me",
+        ] {
+            let uri = DirectoryUri::parse(uri).unwrap();
+            assert_eq!(uri.path(), &PathBuf::from("/home/me"));
+            assert_eq!(uri.target(), "directory:///home/me");
+        }
+        let uri = DirectoryUri::parse("directory://localhost").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/"));
+
+        // A longer name is a server
+        let uri = DirectoryUri::parse("directory://localhost2/share").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("\\\\localhost2\\share"));
+    }
+
+    #[test]
+    fn test_local_drive_path() {
+        for uri in [
+            "directory://localhost/C:/Users/me",
+            "directory:///C:/Users/me",
+            "directory://localhost/C/Users/me",
+        ] {
+            let uri = DirectoryUri::parse(uri).unwrap();
+            assert_eq!(uri.path(), &PathBuf::from("C:\\Users\\me"));
+        }
+        // A colon later in a Unix name is not a drive
+        let uri = DirectoryUri::parse("directory:///a:b/c").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/a:b/c"));
+    }
+
+    #[test]
+    fn test_own_hostname_is_local() {
+        let options = ParseOptions {
+            fallbacks: &[],
+            hostname: Some("mybox.example.com"),
+        };
+        for uri in [
+            "directory://mybox/home/me",
+            "directory://MYBOX.example.com/home/me",
+        ] {
+            let uri = DirectoryUri::parse_with(uri, &options).unwrap();
+            assert_eq!(uri.path(), &PathBuf::from("/home/me"));
+        }
+        let uri = DirectoryUri::parse_with("directory://otherbox/share", &options).unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("\\\\otherbox\\share"));
+
+        // Without the option, the own name is a server like any other
+        let uri = DirectoryUri::parse("directory://mybox/home/me").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("\\\\mybox\\home\\me"));
+    }
+
+    #[test]
+    fn test_parse_options_from_config() {
+        let mut config = UriConfig::default();
+        assert_eq!(config.parse_options(Some("mybox")).hostname, None);
+        config.local_hostname = true;
+        assert_eq!(config.parse_options(Some("mybox")).hostname, Some("mybox"));
+    }
+
     // Special characters tests
     #[test]
     fn test_percent_encoded_special_chars() {
@@ -277,6 +762,128 @@ mod tests {
         assert_eq!(uri.path(), &PathBuf::from("/home/user/テスト"));
     }
 
+    fn fallbacks(labels: &[&str]) -> Vec<FallbackEncoding> {
+        labels
+            .iter()
+            .map(|label| FallbackEncoding::for_label(label).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_cp932_fallback() {
+        let cp932 = fallbacks(&["cp932"]);
+        // 共有/資料 and デスクトップ in CP932
+        let uri =
+            parse_with_fallbacks("directory:///srv/%8B%A4%97%4C/%8E%91%97%BF", &cp932).unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/srv/共有/資料"));
+        assert_eq!(
+            uri.encoding().map(FallbackEncoding::name),
+            Some("Shift_JIS")
+        );
+
+        let uri = parse_with_fallbacks(
+            "directory:///home/user/%83%66%83%58%83%4E%83%67%83%62%83%76",
+            &cp932,
+        )
+        .unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/home/user/デスクトップ"));
+
+        // 表示: the second byte of 表 is 0x5C, which must not split the UNC path
+        let uri = parse_with_fallbacks("directory://server/share/%95%5C%8E%A6", &cp932).unwrap();
+        assert_eq!(uri.path(), &PathBuf::from(r"\\server\share\表示"));
+        assert_eq!(uri.target(), "directory://server/share/%E8%A1%A8%E7%A4%BA");
+    }
+
+    #[test]
+    fn test_utf8_preferred_over_fallbacks() {
+        let uri = parse_with_fallbacks(
+            "directory:///home/user/%E3%83%86%E3%82%B9%E3%83%88",
+            &fallbacks(&["cp932"]),
+        )
+        .unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/home/user/テスト"));
+        assert_eq!(uri.encoding(), None);
+    }
+
+    #[test]
+    fn test_fallbacks_tried_in_order() {
+        // 0x85 0x40 is unmapped in CP932, so Latin-1 (windows-1252) decodes it
+        let uri = parse_with_fallbacks(
+            "directory:///srv/Caf%E9/%85%40",
+            &fallbacks(&["cp932", "latin1"]),
+        )
+        .unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/srv/Café/…@"));
+        assert_eq!(
+            uri.encoding().map(FallbackEncoding::name),
+            Some("windows-1252")
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_without_fallbacks() {
+        // Windows paths are Unicode; raw bytes are only kept for Unix paths
+        for input in [
+            "directory://server/share/%8E%91%97%BF",
+            "directory://C:/%8E%91%97%BF",
+            "directory:///C/%8E%91%97%BF",
+        ] {
+            let result = DirectoryUri::parse(input);
+            assert!(matches!(result, Err(QmpoError::InvalidUri(_))), "{input}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_unix_path_kept_as_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let input = "directory:///srv/archive/%FF%FEold%20name";
+        let uri = DirectoryUri::parse(input).unwrap();
+        assert_eq!(
+            uri.path().as_os_str(),
+            OsStr::from_bytes(b"/srv/archive/\xff\xfeold name")
+        );
+        assert_eq!(uri.encoding(), None);
+        assert_eq!(uri.to_string(), input);
+
+        // A fallback that decodes the bytes takes precedence
+        let uri =
+            parse_with_fallbacks("directory:///srv/%8E%91%97%BF", &fallbacks(&["cp932"])).unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/srv/資料"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_from_non_utf8_unix_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/srv/\x8e\x91\x97\xbf/%x"));
+        let uri = DirectoryUri::from_path(path).unwrap();
+        assert_eq!(uri.to_string(), "directory:///srv/%8E%91%97%BF/%25x");
+        assert_eq!(DirectoryUri::parse(&uri.to_string()).unwrap().path(), path);
+
+        let relative = Path::new(OsStr::from_bytes(b"srv/\xff"));
+        assert!(DirectoryUri::from_path(relative).is_err());
+    }
+
+    #[test]
+    fn test_fallback_encoding_labels() {
+        assert_eq!(
+            FallbackEncoding::for_label("CP932").map(FallbackEncoding::name),
+            Some("Shift_JIS")
+        );
+        assert_eq!(
+            FallbackEncoding::for_label("windows-31j").map(FallbackEncoding::name),
+            Some("Shift_JIS")
+        );
+        assert!(FallbackEncoding::for_label("utf-16le").is_none());
+        assert!(FallbackEncoding::for_label("utf-8").is_none());
+        assert!(FallbackEncoding::for_label("klingon").is_none());
+    }
+
     #[test]
     fn test_path_with_dots() {
         // Note: canonicalize() is done at runtime, not during parsing
@@ -284,6 +891,153 @@ mod tests {
         assert_eq!(uri.path(), &PathBuf::from("/home/user/../other"));
     }
 
+    // Query and signature tests
+    #[test]
+    fn test_query_not_part_of_path() {
+        let uri = DirectoryUri::parse("directory:///home/user?exp=100&sig=abcd").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/home/user"));
+        assert_eq!(
+            uri.signature(),
+            Some(&Signature {
+                sig: "abcd".to_string(),
+                exp: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_no_signature() {
+        let uri = DirectoryUri::parse("directory:///home/user").unwrap();
+        assert_eq!(uri.signature(), None);
+    }
+
+    #[test]
+    fn test_encoded_question_mark_stays_in_path() {
+        let uri = DirectoryUri::parse("directory:///home/user/why%3F").unwrap();
+        assert_eq!(uri.path(), &PathBuf::from("/home/user/why?"));
+        assert_eq!(uri.signature(), None);
+    }
+
+    #[test]
+    fn test_sig_without_exp() {
+        let result = DirectoryUri::parse("directory:///home/user?sig=abcd");
+        assert!(matches!(result, Err(QmpoError::InvalidUri(_))));
+    }
+
+    #[test]
+    fn test_invalid_exp() {
+        let result = DirectoryUri::parse("directory:///home/user?exp=soon&sig=abcd");
+        assert!(matches!(result, Err(QmpoError::InvalidUri(_))));
+    }
+
+    // Encoding tests
+    #[test]
+    fn test_target_normalizes_drive_letter() {
+        let uri = DirectoryUri::parse("directory://C/Users/tagawa").unwrap();
+        assert_eq!(uri.target(), "directory://C:/Users/tagawa");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for input in [
+            "directory:///home/tagawa/My%20Documents",
+            "directory:///home/user/%E3%83%86%E3%82%B9%E3%83%88",
+            "directory:///home/user/%23folder%3F",
+            "directory:///",
+            "directory://C:/Users/tagawa",
+            "directory://D:/",
+            "directory://server/share/folder",
+        ] {
+            let uri = DirectoryUri::parse(input).unwrap();
+            assert_eq!(uri.to_string(), input);
+            assert_eq!(DirectoryUri::parse(&uri.to_string()).unwrap(), uri);
+        }
+    }
+
+    #[test]
+    fn test_windows_namespace_roundtrip() {
+        for (path, target) in [
+            (r"\\?\C:\Users\me", "directory://%3F/C:/Users/me"),
+            (
+                r"\\?\UNC\fs01\share\dir",
+                "directory://%3F/UNC/fs01/share/dir",
+            ),
+            (r"\\.\PhysicalDrive0", "directory://./PhysicalDrive0"),
+            (
+                r"\\?\Volume{b75e2c83}\dir",
+                "directory://%3F/Volume%7Bb75e2c83%7D/dir",
+            ),
+            (r"\\fs01\C$\logs", "directory://fs01/C$/logs"),
+        ] {
+            let uri = DirectoryUri::from_path(Path::new(path)).unwrap();
+            assert_eq!(uri.target(), target);
+            assert_eq!(DirectoryUri::parse(target).unwrap().path(), Path::new(path));
+        }
+    }
+
+    #[test]
+    fn test_webdav_unc() {
+        let input = "directory://files.example.com@SSL@443/DavWWWRoot/team/Q1%20report";
+        let uri = DirectoryUri::parse(input).unwrap();
+        assert_eq!(
+            uri.path(),
+            Path::new(r"\\files.example.com@SSL@443\DavWWWRoot\team\Q1 report")
+        );
+        assert_eq!(uri.target(), input);
+        assert_eq!(
+            uri.webdav().unwrap().url(),
+            "https://files.example.com/team/Q1%20report"
+        );
+        assert_eq!(
+            DirectoryUri::parse("directory://server/share")
+                .unwrap()
+                .webdav(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_placeholder_kept_unexpanded() {
+        for input in [
+            "directory:///~/notes",
+            "directory://known/Downloads/a.zip",
+            "directory://env/PROJECTS/qmpo",
+        ] {
+            let uri = DirectoryUri::parse(input).unwrap();
+            assert!(uri.placeholder().is_some(), "{input}");
+            assert_eq!(uri.target(), input);
+        }
+        assert!(
+            DirectoryUri::parse("directory:///home/me")
+                .unwrap()
+                .placeholder()
+                .is_none()
+        );
+        assert!(DirectoryUri::parse("directory://known/Music").is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_with_signature() {
+        let input = "directory:///srv/share?exp=1700000000&sig=00ff";
+        let uri = DirectoryUri::parse(input).unwrap();
+        assert_eq!(uri.to_string(), input);
+    }
+
+    #[test]
+    fn test_from_path() {
+        let uri = DirectoryUri::from_path(Path::new("\\\\server\\share\\a b")).unwrap();
+        assert_eq!(uri.to_string(), "directory://server/share/a%20b");
+
+        let uri = DirectoryUri::from_path(Path::new("C:\\Users")).unwrap();
+        assert_eq!(uri.to_string(), "directory://C:/Users");
+    }
+
+    #[test]
+    fn test_from_relative_path() {
+        let result = DirectoryUri::from_path(Path::new("relative/dir"));
+        assert!(matches!(result, Err(QmpoError::InvalidUri(_))));
+    }
+
     // Error tests
     #[test]
     fn test_invalid_scheme() {
@@ -0,0 +1,326 @@
+//! WebDAV folders, as named in UNC paths and URLs.
+//!
+//! Windows reaches WebDAV servers (e.g. SharePoint) through UNC paths whose
+//! server part carries the transport: `\\files.example.com@SSL@443\DavWWWRoot\team`
+//! is `https://files.example.com/team`. `@SSL` selects HTTPS, a numeric suffix
+//! the port, and the `DavWWWRoot` share stands for the server's root.
+//!
+//! On Linux, gvfs mounts the same folder as `davs://files.example.com/team` and
+//! exposes it under `$XDG_RUNTIME_DIR/gvfs` (see [`WebDav::gvfs_dir`]).
+
+use std::path::{Path, PathBuf};
+
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+
+use crate::winpath::{Root, WinPath};
+
+/// The share that stands for the server's root in WebDAV UNC paths.
+const ROOT_SHARE: &str = "DavWWWRoot";
+
+/// Characters escaped in URL path segments.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Characters escaped in gvfs mount names, which leave `$&'()*+` as they are.
+const GVFS: &AsciiSet = &SEGMENT
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+');
+
+/// A folder on a WebDAV server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebDav {
+    /// The host name, lowercased.
+    pub host: String,
+    /// Whether the server is reached over HTTPS.
+    pub ssl: bool,
+    /// The port, if not the default for the transport.
+    pub port: Option<u16>,
+    /// The decoded path segments below the server's root.
+    pub segments: Vec<String>,
+}
+
+impl WebDav {
+    /// Parse a WebDAV UNC path (with `\` or `/` separators) or an `http`,
+    /// `https`, `dav` or `davs` URL.
+    ///
+    /// A UNC path is WebDAV if its server has an `@SSL` or `@port` suffix, or
+    /// its share is `DavWWWRoot`; other UNC paths are SMB shares.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.starts_with("\\\\") || s.starts_with("//") {
+            let unc = s.replace('/', "\\");
+            Self::from_unc(&WinPath::parse(&unc)?)
+        } else {
+            Self::from_url(s)
+        }
+    }
+
+    /// The WebDAV folder a UNC path names, if it names one.
+    pub fn from_unc(path: &WinPath<'_>) -> Option<Self> {
+        let Root::Unc { server, share } = path.root else {
+            return None;
+        };
+        let mut parts = server.split('@');
+        let host = parts.next()?;
+        let mut ssl = false;
+        let mut port = None;
+        for part in parts {
+            if part.eq_ignore_ascii_case("SSL") {
+                ssl = true;
+            } else {
+                port = Some(part.parse().ok()?);
+            }
+        }
+
+        let is_root = share.eq_ignore_ascii_case(ROOT_SHARE);
+        if host.is_empty() || (!server.contains('@') && !is_root) {
+            return None;
+        }
+        let share = (!is_root).then_some(share);
+        let segments = share
+            .into_iter()
+            .chain(path.rest.split('\\'))
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        Some(Self::new(host, ssl, port, segments))
+    }
+
+    /// The WebDAV folder an `http`, `https`, `dav` or `davs` URL names.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let ssl = match scheme.to_ascii_lowercase().as_str() {
+            "https" | "davs" => true,
+            "http" | "dav" => false,
+            _ => return None,
+        };
+        let (authority, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+        // Credentials do not belong in config files or links
+        if authority.contains('@') {
+            return None;
+        }
+        let (host, port) = match authority.rsplit_once(':') {
+            // The colons of an IPv6 address are not a port separator
+            Some((host, port)) if !port.ends_with(']') => (host, Some(port.parse().ok()?)),
+            _ => (authority, None),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let segments = path
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                percent_decode_str(segment)
+                    .decode_utf8()
+                    .ok()
+                    .map(|segment| segment.into_owned())
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::new(host, ssl, port, segments))
+    }
+
+    fn new(host: &str, ssl: bool, port: Option<u16>, segments: Vec<String>) -> Self {
+        let default_port = if ssl { 443 } else { 80 };
+        Self {
+            host: host.to_ascii_lowercase(),
+            ssl,
+            port: port.filter(|&port| port != default_port),
+            segments,
+        }
+    }
+
+    /// The segments below `prefix`, if this folder is on the same server and
+    /// inside it. Segments compare case-insensitively, as on Windows.
+    pub fn strip_prefix(&self, prefix: &WebDav) -> Option<&[String]> {
+        let same_server =
+            self.host == prefix.host && self.ssl == prefix.ssl && self.port == prefix.port;
+        if !same_server || self.segments.len() < prefix.segments.len() {
+            return None;
+        }
+        let (head, rest) = self.segments.split_at(prefix.segments.len());
+        head.iter()
+            .zip(&prefix.segments)
+            .all(|(a, b)| a.to_lowercase() == b.to_lowercase())
+            .then_some(rest)
+    }
+
+    /// The folder as an `http` or `https` URL.
+    pub fn url(&self) -> String {
+        let scheme = if self.ssl { "https" } else { "http" };
+        format!("{scheme}://{}{}", self.authority(), self.path())
+    }
+
+    /// Where gvfs exposes this folder once it is mounted as `davs://` (or
+    /// `dav://`) with exactly this path, below `gvfs_root`
+    /// (normally `$XDG_RUNTIME_DIR/gvfs`).
+    pub fn gvfs_dir(&self, gvfs_root: &Path) -> PathBuf {
+        let mut name = format!("dav:host={}", gvfs_escape(&self.host));
+        if let Some(port) = self.port {
+            name.push_str(&format!(",port={port}"));
+        }
+        name.push_str(if self.ssl { ",ssl=true" } else { ",ssl=false" });
+        if !self.segments.is_empty() {
+            let prefix = format!("/{}", self.segments.join("/"));
+            name.push_str(&format!(",prefix={}", gvfs_escape(&prefix)));
+        }
+        gvfs_root.join(name)
+    }
+
+    fn authority(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{port}", self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// The segments as an absolute URL path.
+    fn path(&self) -> String {
+        if self.segments.is_empty() {
+            return "/".to_string();
+        }
+        self.segments
+            .iter()
+            .map(|segment| format!("/{}", utf8_percent_encode(segment, SEGMENT)))
+            .collect()
+    }
+}
+
+/// Escape a gvfs mount name value. Like GLib, this keeps non-ASCII characters.
+fn gvfs_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii() {
+            escaped.extend(utf8_percent_encode(c.encode_utf8(&mut [0; 1]), GVFS));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// The directory gvfs exposes its mounts in, if known.
+pub fn gvfs_root() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("gvfs"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dav(host: &str, ssl: bool, port: Option<u16>, segments: &[&str]) -> WebDav {
+        WebDav {
+            host: host.to_string(),
+            ssl,
+            port,
+            segments: segments.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_unc_forms() {
+        let team = dav("files.example.com", true, None, &["team"]);
+        for path in [
+            r"\\files.example.com@SSL@443\DavWWWRoot\team",
+            r"\\Files.Example.com@ssl\DavWWWRoot\team\",
+            r"\\files.example.com@SSL\team",
+            "//files.example.com@SSL/DavWWWRoot/team",
+        ] {
+            assert_eq!(WebDav::parse(path), Some(team.clone()), "{path}");
+        }
+        assert_eq!(
+            WebDav::parse(r"\\intranet@8080\DavWWWRoot"),
+            Some(dav("intranet", false, Some(8080), &[]))
+        );
+        assert_eq!(
+            WebDav::parse(r"\\intranet\DavWWWRoot\a b"),
+            Some(dav("intranet", false, None, &["a b"]))
+        );
+
+        // SMB shares, and server suffixes that are not WebDAV's
+        for path in [
+            r"\\fileserver\share\dir",
+            r"\\fileserver@TLS\share",
+            r"\\?\C:\dir",
+        ] {
+            assert_eq!(WebDav::parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_url_forms() {
+        let team = dav("files.example.com", true, None, &["team", "Q1 report"]);
+        for url in [
+            "https://files.example.com/team/Q1%20report",
+            "https://files.example.com:443/team/Q1%20report/",
+            "davs://FILES.example.com/team/Q1%20report",
+        ] {
+            assert_eq!(WebDav::parse(url), Some(team.clone()), "{url}");
+        }
+        assert_eq!(
+            WebDav::parse("dav://intranet:8080/"),
+            Some(dav("intranet", false, Some(8080), &[]))
+        );
+        for url in [
+            "ftp://files.example.com/team",
+            "https://user@files.example.com/team",
+            "https://files.example.com:port/team",
+        ] {
+            assert_eq!(WebDav::parse(url), None, "{url}");
+        }
+
+        assert_eq!(team.url(), "https://files.example.com/team/Q1%20report");
+        assert_eq!(
+            dav("intranet", false, Some(8080), &[]).url(),
+            "http://intranet:8080/"
+        );
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let prefix = WebDav::parse("https://files.example.com/Team").unwrap();
+        let path = WebDav::parse(r"\\files.example.com@SSL\DavWWWRoot\team\a\b").unwrap();
+        assert_eq!(
+            path.strip_prefix(&prefix),
+            Some(&["a".to_string(), "b".to_string()][..])
+        );
+
+        let http = WebDav::parse(r"\\files.example.com\DavWWWRoot\team\a").unwrap();
+        assert_eq!(http.strip_prefix(&prefix), None);
+        let other = WebDav::parse("https://files.example.com/teams/a").unwrap();
+        assert_eq!(other.strip_prefix(&prefix), None);
+    }
+
+    #[test]
+    fn test_gvfs_dir() {
+        let root = Path::new("/run/user/1000/gvfs");
+        assert_eq!(
+            WebDav::parse("davs://files.example.com/sites/Team Site")
+                .unwrap()
+                .gvfs_dir(root),
+            root.join("dav:host=files.example.com,ssl=true,prefix=%2Fsites%2FTeam%20Site")
+        );
+        assert_eq!(
+            WebDav::parse("davs://files.example.com/共有")
+                .unwrap()
+                .gvfs_dir(root),
+            root.join("dav:host=files.example.com,ssl=true,prefix=%2F共有")
+        );
+        assert_eq!(
+            WebDav::parse("dav://intranet:8080/")
+                .unwrap()
+                .gvfs_dir(root),
+            root.join("dav:host=intranet,port=8080,ssl=false")
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Windows path forms beyond `C:\dir` and `\\server\share\dir`.
+//!
+//! Windows also accepts paths in the Win32 file namespace (`\\?\C:\dir`,
+//! `\\?\UNC\server\share\dir`), which are passed to the filesystem verbatim and
+//! escape the `MAX_PATH` limit, and in the device namespace (`\\.\C:\dir`,
+//! `\\.\PhysicalDrive0`). `canonicalize()` returns the `\\?\` form, which
+//! `explorer.exe /select,` and many other programs mishandle; [`simplify`]
+//! turns it back into a plain path where that means the same thing.
+//!
+//! Everything here works on strings, so it behaves the same on every platform.
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Plain Win32 paths must be shorter than this.
+const MAX_PATH: usize = 260;
+
+/// Names Win32 maps to devices in any directory, with or without extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The namespace a path is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    /// A plain path, normalized by Windows before use.
+    Win32,
+    /// `\\?\`: passed to the filesystem verbatim.
+    Verbatim,
+    /// `\\.\`: the device namespace.
+    Device,
+}
+
+/// What a path starts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Root<'a> {
+    /// A drive letter, e.g. `C` in `C:\dir`.
+    Drive(char),
+    /// A share on a server, e.g. `\\server\share`. Admin shares like `C$` are
+    /// shares too (see [`WinPath::admin_drive`]).
+    Unc { server: &'a str, share: &'a str },
+    /// Another device or volume, e.g. `PhysicalDrive0` or `Volume{…}`.
+    Device(&'a str),
+}
+
+/// A Windows path split into its namespace, root and the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinPath<'a> {
+    pub namespace: Namespace,
+    pub root: Root<'a>,
+    /// Everything after the root, e.g. `\dir` in `C:\dir`; may be empty.
+    pub rest: &'a str,
+}
+
+impl<'a> WinPath<'a> {
+    /// Split a path written with `\` separators.
+    ///
+    /// Returns `None` for paths that are not Windows paths, such as Unix paths
+    /// or a server without a share.
+    pub fn parse(path: &'a str) -> Option<Self> {
+        let (namespace, body) = if let Some(body) = path.strip_prefix(r"\\?\") {
+            (Namespace::Verbatim, body)
+        } else if let Some(body) = path.strip_prefix(r"\\.\") {
+            (Namespace::Device, body)
+        } else if let Some(body) = path.strip_prefix(r"\\") {
+            return Self::parse_unc(Namespace::Win32, body);
+        } else {
+            (Namespace::Win32, path)
+        };
+
+        if let [letter, b':', ..] = body.as_bytes()
+            && letter.is_ascii_alphabetic()
+        {
+            return Some(Self {
+                namespace,
+                root: Root::Drive(char::from(*letter)),
+                rest: &body[2..],
+            });
+        }
+        if namespace == Namespace::Win32 {
+            return None;
+        }
+        if let Some(unc) = body
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case(r"UNC\"))
+            .map(|prefix| &body[prefix.len()..])
+        {
+            return Self::parse_unc(namespace, unc);
+        }
+        let (name, rest) = split_first(body);
+        (!name.is_empty()).then_some(Self {
+            namespace,
+            root: Root::Device(name),
+            rest,
+        })
+    }
+
+    fn parse_unc(namespace: Namespace, body: &'a str) -> Option<Self> {
+        let (server, body) = split_first(body);
+        let (share, rest) = split_first(body.strip_prefix('\\')?);
+        if server.is_empty() || share.is_empty() {
+            return None;
+        }
+        Some(Self {
+            namespace,
+            root: Root::Unc { server, share },
+            rest,
+        })
+    }
+
+    /// The drive an admin share such as `\\server\C$` exposes.
+    pub fn admin_drive(&self) -> Option<char> {
+        let Root::Unc { share, .. } = self.root else {
+            return None;
+        };
+        match share.as_bytes() {
+            [letter, b'$'] if letter.is_ascii_alphabetic() => Some(char::from(*letter)),
+            _ => None,
+        }
+    }
+
+    /// The same location as a plain Win32 path, if there is one.
+    ///
+    /// A `\\?\` path keeps its prefix when Win32 normalization would change
+    /// its meaning (`..`, trailing dots or spaces, device names like `NUL`) or
+    /// it is too long. Other devices and volumes have no plain form. An admin
+    /// share on `localhost` becomes its drive. `\\.\C:` names the volume, not
+    /// a directory, and keeps its prefix; `\\?\C:` becomes the drive's root
+    /// `C:\`, as plain `C:` means the current directory on that drive.
+    pub fn simplified(self) -> Self {
+        let plain = match self.root {
+            Root::Device(_) => return self,
+            Root::Drive(_) if self.rest.is_empty() && self.namespace != Namespace::Win32 => {
+                if self.namespace == Namespace::Device {
+                    return self;
+                }
+                Self {
+                    namespace: Namespace::Win32,
+                    rest: r"\",
+                    ..self
+                }
+            }
+            Root::Unc { server, .. } if is_loopback(server) => match self.admin_drive() {
+                Some(letter) => Self {
+                    namespace: Namespace::Win32,
+                    root: Root::Drive(letter),
+                    rest: if self.rest.is_empty() {
+                        r"\"
+                    } else {
+                        self.rest
+                    },
+                },
+                None => Self {
+                    namespace: Namespace::Win32,
+                    ..self
+                },
+            },
+            _ => Self {
+                namespace: Namespace::Win32,
+                ..self
+            },
+        };
+        if self.namespace == Namespace::Verbatim && !survives_normalization(self.rest) {
+            return self;
+        }
+        if plain.to_string().len() >= MAX_PATH {
+            return self;
+        }
+        plain
+    }
+}
+
+impl fmt::Display for WinPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = match self.namespace {
+            Namespace::Win32 => "",
+            Namespace::Verbatim => r"\\?\",
+            Namespace::Device => r"\\.\",
+        };
+        match self.root {
+            Root::Drive(letter) => write!(f, "{prefix}{letter}:")?,
+            Root::Unc { server, share } if self.namespace == Namespace::Win32 => {
+                write!(f, r"\\{server}\{share}")?;
+            }
+            Root::Unc { server, share } => write!(f, r"{prefix}UNC\{server}\{share}")?,
+            Root::Device(name) => write!(f, "{prefix}{name}")?,
+        }
+        f.write_str(self.rest)
+    }
+}
+
+/// Simplify a Windows path for programs that only understand plain paths.
+///
+/// Anything that is not a Windows path, or has no simpler form, is returned
+/// unchanged.
+pub fn simplify(path: &str) -> Cow<'_, str> {
+    match WinPath::parse(path) {
+        Some(parsed) => {
+            let simplified = parsed.simplified();
+            if simplified == parsed {
+                Cow::Borrowed(path)
+            } else {
+                Cow::Owned(simplified.to_string())
+            }
+        }
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Split at the first `\`, which stays with the second part.
+fn split_first(s: &str) -> (&str, &str) {
+    s.find('\\').map_or((s, ""), |i| s.split_at(i))
+}
+
+fn is_loopback(server: &str) -> bool {
+    server.eq_ignore_ascii_case("localhost") || server == "127.0.0.1"
+}
+
+/// Whether Win32 normalization leaves every component of `rest` as it is.
+fn survives_normalization(rest: &str) -> bool {
+    let rest = rest.strip_prefix('\\').unwrap_or(rest);
+    let mut components = rest.split('\\').peekable();
+    while let Some(component) = components.next() {
+        let last = components.peek().is_none();
+        if component.is_empty() {
+            // Only a trailing separator is kept; doubled ones are collapsed
+            if !last {
+                return false;
+            }
+            continue;
+        }
+        if component.contains('/') || component.ends_with(['.', ' ']) {
+            return false;
+        }
+        let stem = component.split('.').next().unwrap_or(component);
+        if RESERVED_NAMES
+            .iter()
+            .any(|name| stem.trim_end().eq_ignore_ascii_case(name))
+        {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forms() {
+        let cases = [
+            (r"C:\Users", Namespace::Win32, Root::Drive('C'), r"\Users"),
+            (
+                r"\\?\C:\Users",
+                Namespace::Verbatim,
+                Root::Drive('C'),
+                r"\Users",
+            ),
+            (r"\\.\d:", Namespace::Device, Root::Drive('d'), ""),
+            (
+                r"\\fs01\C$\logs",
+                Namespace::Win32,
+                Root::Unc {
+                    server: "fs01",
+                    share: "C$",
+                },
+                r"\logs",
+            ),
+            (
+                r"\\?\UNC\fs01\share\dir",
+                Namespace::Verbatim,
+                Root::Unc {
+                    server: "fs01",
+                    share: "share",
+                },
+                r"\dir",
+            ),
+            (
+                r"\\.\PhysicalDrive0",
+                Namespace::Device,
+                Root::Device("PhysicalDrive0"),
+                "",
+            ),
+            (
+                r"\\?\Volume{b75e2c83-0000-0000-0000-602f00000000}\dir",
+                Namespace::Verbatim,
+                Root::Device("Volume{b75e2c83-0000-0000-0000-602f00000000}"),
+                r"\dir",
+            ),
+        ];
+        for (path, namespace, root, rest) in cases {
+            let parsed = WinPath::parse(path).unwrap();
+            assert_eq!(
+                parsed,
+                WinPath {
+                    namespace,
+                    root,
+                    rest
+                },
+                "{path}"
+            );
+            assert_eq!(parsed.to_string(), path);
+        }
+
+        for path in ["/home/me", r"\\server", r"\\?\", "relative"] {
+            assert_eq!(WinPath::parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_admin_drive() {
+        let drive = |path| WinPath::parse(path).unwrap().admin_drive();
+        assert_eq!(drive(r"\\fs01\C$\logs"), Some('C'));
+        assert_eq!(drive(r"\\?\UNC\fs01\d$"), Some('d'));
+        assert_eq!(drive(r"\\fs01\ADMIN$"), None);
+        assert_eq!(drive(r"C:\"), None);
+    }
+
+    #[test]
+    fn test_simplify() {
+        let cases = [
+            (r"\\?\C:\Users\me", r"C:\Users\me"),
+            (r"\\?\C:\", r"C:\"),
+            (r"\\?\C:", r"C:\"),
+            (r"\\?\UNC\fs01\share\dir", r"\\fs01\share\dir"),
+            (r"\\.\C:\Users", r"C:\Users"),
+            (r"\\localhost\C$\logs", r"C:\logs"),
+            (r"\\?\UNC\LOCALHOST\c$", r"c:\"),
+            // Already plain, or no plain form
+            (r"C:\Users", r"C:\Users"),
+            (r"\\fs01\C$\logs", r"\\fs01\C$\logs"),
+            (r"\\.\PhysicalDrive0", r"\\.\PhysicalDrive0"),
+            // The volume itself, not a directory on it
+            (r"\\.\d:", r"\\.\d:"),
+            (r"\\?\Volume{b75e2c83}\dir", r"\\?\Volume{b75e2c83}\dir"),
+            ("/home/me", "/home/me"),
+            // Win32 would read these differently
+            (r"\\?\C:\dir\..\x", r"\\?\C:\dir\..\x"),
+            (r"\\?\C:\trailing.", r"\\?\C:\trailing."),
+            (r"\\?\C:\space \x", r"\\?\C:\space \x"),
+            (r"\\?\C:\dir\nul.txt", r"\\?\C:\dir\nul.txt"),
+            (r"\\?\C:\a\\b", r"\\?\C:\a\\b"),
+        ];
+        for (path, simplified) in cases {
+            assert_eq!(simplify(path), simplified, "{path}");
+        }
+
+        let long = format!(r"\\?\C:\{}", "a".repeat(MAX_PATH));
+        assert_eq!(simplify(&long), long);
+    }
+}
@@ -0,0 +1,241 @@
+//! Path translation for the Windows Subsystem for Linux.
+//!
+//! Inside WSL, Windows drives are mounted under `/mnt` (`C:\Users` is
+//! `/mnt/c/Users`), and Windows reaches the distribution's files through
+//! `\\wsl.localhost\<distro>\...` (formerly `\\wsl$\<distro>\...`). Links
+//! written with either form are translated to the Linux path, and the file
+//! manager is Explorer, started through interop with the Windows path.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::winpath::{self, Root, WinPath};
+
+/// binfmt_misc entries that run Windows programs; older WSL has only the first.
+const INTEROP_FILES: [&str; 2] = [
+    "/proc/sys/fs/binfmt_misc/WSLInterop",
+    "/proc/sys/fs/binfmt_misc/WSLInterop-late",
+];
+
+/// Servers Windows reaches WSL distributions through.
+const SERVERS: [&str; 2] = ["wsl.localhost", "wsl$"];
+
+/// The WSL environment qmpo runs in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wsl {
+    /// The distribution's name, from `WSL_DISTRO_NAME`.
+    pub distro: Option<String>,
+    /// Whether Windows programs can be started.
+    pub interop: bool,
+    /// Where Windows drives are mounted: `/mnt` unless `/etc/wsl.conf` says otherwise.
+    pub mount_root: PathBuf,
+}
+
+/// The WSL environment, detected once.
+pub fn current() -> Option<&'static Wsl> {
+    static WSL: OnceLock<Option<Wsl>> = OnceLock::new();
+    WSL.get_or_init(|| {
+        let interop = INTEROP_FILES
+            .iter()
+            .find_map(|file| fs::read_to_string(file).ok());
+        detect(
+            interop.as_deref(),
+            std::env::var_os("WSL_DISTRO_NAME").as_deref(),
+            fs::read_to_string("/etc/wsl.conf").ok().as_deref(),
+        )
+    })
+    .as_ref()
+}
+
+/// Detect WSL from the contents of the `WSLInterop` entry, `WSL_DISTRO_NAME`
+/// and `/etc/wsl.conf`, any of which may be missing.
+fn detect(interop: Option<&str>, distro: Option<&OsStr>, wsl_conf: Option<&str>) -> Option<Wsl> {
+    let distro = distro
+        .and_then(OsStr::to_str)
+        .filter(|distro| !distro.is_empty());
+    if interop.is_none() && distro.is_none() {
+        return None;
+    }
+    Some(Wsl {
+        distro: distro.map(str::to_string),
+        interop: interop.is_some_and(|entry| entry.lines().next() == Some("enabled")),
+        mount_root: wsl_conf
+            .and_then(automount_root)
+            .unwrap_or_else(|| PathBuf::from("/mnt")),
+    })
+}
+
+/// The `root` setting of the `[automount]` section of `wsl.conf`.
+fn automount_root(wsl_conf: &str) -> Option<PathBuf> {
+    let mut section = "";
+    for line in wsl_conf.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim();
+        } else if let Some((key, value)) = line.split_once('=')
+            && section.eq_ignore_ascii_case("automount")
+            && key.trim() == "root"
+        {
+            let value = value.trim().trim_matches('"');
+            return value.starts_with('/').then(|| PathBuf::from(value));
+        }
+    }
+    None
+}
+
+impl Wsl {
+    /// The Linux path for a Windows drive path, or for a path in this
+    /// distribution's `\\wsl.localhost\` share.
+    pub fn to_linux(&self, path: &str) -> Option<PathBuf> {
+        let path = winpath::simplify(path);
+        let parsed = WinPath::parse(&path)?;
+        let mut linux = match parsed.root {
+            Root::Drive(letter) => self
+                .mount_root
+                .join(letter.to_ascii_lowercase().to_string()),
+            Root::Unc { server, share }
+                if SERVERS.iter().any(|s| s.eq_ignore_ascii_case(server))
+                    && self
+                        .distro
+                        .as_deref()
+                        .is_some_and(|distro| distro.eq_ignore_ascii_case(share)) =>
+            {
+                PathBuf::from("/")
+            }
+            _ => return None,
+        };
+        for component in parsed.rest.split('\\').filter(|c| !c.is_empty()) {
+            linux.push(component);
+        }
+        Some(linux)
+    }
+
+    /// The Windows path for an absolute Linux path: a drive path under the
+    /// mount root, or else a path in this distribution's `\\wsl.localhost\` share.
+    pub fn to_windows(&self, path: &Path) -> Option<String> {
+        if !path.is_absolute() {
+            return None;
+        }
+        if let Ok(rest) = path.strip_prefix(&self.mount_root) {
+            let mut components = rest.components();
+            if let Some(Component::Normal(drive)) = components.next()
+                && let [letter] = drive.as_encoded_bytes()
+                && letter.is_ascii_alphabetic()
+            {
+                let rest = join_windows(components.as_path())?;
+                return Some(format!("{}:\\{rest}", letter.to_ascii_uppercase() as char));
+            }
+        }
+        let distro = self.distro.as_deref()?;
+        let rest = join_windows(path.strip_prefix("/").ok()?)?;
+        Some(format!(r"\\wsl.localhost\{distro}\{rest}"))
+    }
+}
+
+/// Join the components of a relative path with `\`.
+fn join_windows(path: &Path) -> Option<String> {
+    let components = path
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(components.join("\\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ubuntu() -> Wsl {
+        Wsl {
+            distro: Some("Ubuntu".to_string()),
+            interop: true,
+            mount_root: PathBuf::from("/mnt"),
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(None, None, None), None);
+        assert_eq!(detect(None, Some("".as_ref()), None), None);
+
+        let wsl = detect(
+            Some("enabled\ninterpreter /init\n"),
+            Some("Ubuntu".as_ref()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(wsl, ubuntu());
+
+        // Interop switched off, distribution name stripped from the environment
+        let wsl = detect(Some("disabled\n"), None, None).unwrap();
+        assert!(!wsl.interop);
+        assert_eq!(wsl.distro, None);
+
+        let wsl = detect(
+            None,
+            Some("Debian".as_ref()),
+            Some("[boot]\nsystemd=true\n\n[automount]\nenabled = true\nroot = /win/\n"),
+        )
+        .unwrap();
+        assert!(!wsl.interop);
+        assert_eq!(wsl.mount_root, PathBuf::from("/win/"));
+    }
+
+    #[test]
+    fn test_to_linux() {
+        let wsl = ubuntu();
+        let cases = [
+            (r"C:\Users\me", Some("/mnt/c/Users/me")),
+            (r"d:\", Some("/mnt/d")),
+            (r"\\?\C:\Users", Some("/mnt/c/Users")),
+            (r"\\wsl.localhost\Ubuntu\home\me", Some("/home/me")),
+            (r"\\WSL$\ubuntu\etc", Some("/etc")),
+            (r"\\wsl.localhost\Debian\home\me", None),
+            (r"\\fileserver\share", None),
+            ("/home/me", None),
+        ];
+        for (path, linux) in cases {
+            assert_eq!(wsl.to_linux(path), linux.map(PathBuf::from), "{path}");
+        }
+
+        let wsl = Wsl {
+            mount_root: PathBuf::from("/win"),
+            distro: None,
+            ..ubuntu()
+        };
+        assert_eq!(wsl.to_linux(r"C:\x"), Some(PathBuf::from("/win/c/x")));
+        assert_eq!(wsl.to_linux(r"\\wsl.localhost\Ubuntu\home"), None);
+    }
+
+    #[test]
+    fn test_to_windows() {
+        let wsl = ubuntu();
+        let cases = [
+            ("/mnt/c/Users/me", Some(r"C:\Users\me")),
+            ("/mnt/d", Some(r"D:\")),
+            ("/mnt/wsl/x", Some(r"\\wsl.localhost\Ubuntu\mnt\wsl\x")),
+            ("/home/me", Some(r"\\wsl.localhost\Ubuntu\home\me")),
+            ("/", Some(r"\\wsl.localhost\Ubuntu\")),
+            ("relative", None),
+        ];
+        for (path, windows) in cases {
+            assert_eq!(
+                wsl.to_windows(Path::new(path)).as_deref(),
+                windows,
+                "{path}"
+            );
+        }
+
+        let wsl = Wsl {
+            distro: None,
+            ..ubuntu()
+        };
+        assert_eq!(wsl.to_windows(Path::new("/home/me")), None);
+        assert_eq!(
+            wsl.to_windows(Path::new("/mnt/c/x")).as_deref(),
+            Some(r"C:\x")
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Just enough of the X11 protocol to announce a launch.
+//!
+//! X11 window managers give focus to a new window only if they can tell the
+//! user asked for it. Launchers announce each launch with a `new:` message
+//! (the freedesktop startup-notification spec) whose ID ends in
+//! `_TIME<server time>`; the launched program presents the ID when it maps its
+//! window and ends the sequence with `remove:`. The messages are sent to the
+//! root window in 20-byte `ClientMessage` chunks. qmpo has no window of its
+//! own, so it creates an input-only one to send them from and to learn the
+//! server time.
+//!
+//! Only local displays (`:0`, `unix:0.1`) are supported, authenticated with
+//! `MIT-MAGIC-COOKIE-1` from the Xauthority file.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// How long to wait for the X server.
+const TIMEOUT: Duration = Duration::from_secs(1);
+const AUTH_NAME: &[u8] = b"MIT-MAGIC-COOKIE-1";
+/// Xauthority address families.
+const FAMILY_LOCAL: u16 = 256;
+const FAMILY_WILD: u16 = 65535;
+
+// Request opcodes
+const CREATE_WINDOW: u8 = 1;
+const INTERN_ATOM: u8 = 16;
+const CHANGE_PROPERTY: u8 = 18;
+const SEND_EVENT: u8 = 25;
+const GET_INPUT_FOCUS: u8 = 43;
+
+// Event codes
+const PROPERTY_NOTIFY: u8 = 28;
+const CLIENT_MESSAGE: u8 = 33;
+
+const PROPERTY_CHANGE_MASK: u32 = 0x0040_0000;
+/// `CreateWindow` value-mask bit for the event mask.
+const CW_EVENT_MASK: u32 = 0x0800;
+const INPUT_ONLY: u16 = 2;
+const PROP_MODE_APPEND: u8 = 2;
+/// Predefined atoms.
+const ATOM_WM_NAME: u32 = 39;
+const ATOM_STRING: u32 = 31;
+
+/// Sequences started by this process, for unique IDs.
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A startup-notification sequence announced by qmpo.
+pub struct Sequence<S = UnixStream> {
+    conn: Connection<S>,
+    window: u32,
+    begin: u32,
+    more: u32,
+    id: String,
+}
+
+impl Sequence {
+    /// Connect to `$DISPLAY` and announce a launch of `name`.
+    pub fn start(name: &str) -> io::Result<Self> {
+        let display = std::env::var("DISPLAY").map_err(|_| unsupported("DISPLAY is not set"))?;
+        let (number, screen) =
+            parse_display(&display).ok_or_else(|| unsupported("DISPLAY is not a local display"))?;
+        let stream = connect(number)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let host = hostname();
+        let cookie = xauthority()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| cookie(&data, host.as_bytes(), &number.to_string()))
+            .unwrap_or_default();
+        Self::start_on(stream, &cookie, screen, name, &host)
+    }
+}
+
+impl<S: Read + Write> Sequence<S> {
+    fn start_on(
+        stream: S,
+        cookie: &[u8],
+        screen: usize,
+        name: &str,
+        host: &str,
+    ) -> io::Result<Self> {
+        let mut conn = Connection::setup(stream, cookie, screen)?;
+        let begin = conn.intern_atom("_NET_STARTUP_INFO_BEGIN")?;
+        let more = conn.intern_atom("_NET_STARTUP_INFO")?;
+        let window = conn.create_window()?;
+        let time = conn.server_time(window)?;
+
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let id = format!("qmpo-{}-{host}-{n}_TIME{time}", std::process::id());
+        let mut sequence = Self {
+            conn,
+            window,
+            begin,
+            more,
+            id,
+        };
+        let screen = screen.to_string();
+        sequence.send(&message(
+            "new",
+            &[("ID", &sequence.id), ("NAME", name), ("SCREEN", &screen)],
+        ))?;
+        Ok(sequence)
+    }
+
+    /// The startup ID to pass to the launched program.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// End the sequence, for a launch that did not happen.
+    pub fn remove(mut self) -> io::Result<()> {
+        let message = message("remove", &[("ID", &self.id)]);
+        self.send(&message)
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        for (i, chunk) in message.chunks(20).enumerate() {
+            let kind = if i == 0 { self.begin } else { self.more };
+            let mut event = vec![CLIENT_MESSAGE, 8, 0, 0];
+            event.extend(self.window.to_le_bytes());
+            event.extend(kind.to_le_bytes());
+            event.extend(chunk);
+            event.resize(32, 0);
+            self.conn.send_event(&event)?;
+        }
+        self.conn.sync()
+    }
+}
+
+/// A connection to the X server, in little-endian byte order.
+struct Connection<S> {
+    stream: S,
+    id_base: u32,
+    id_mask: u32,
+    root: u32,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn setup(mut stream: S, cookie: &[u8], screen: usize) -> io::Result<Self> {
+        let name: &[u8] = if cookie.is_empty() { b"" } else { AUTH_NAME };
+        let mut request = vec![b'l', 0];
+        request.extend(11u16.to_le_bytes());
+        request.extend(0u16.to_le_bytes());
+        request.extend((name.len() as u16).to_le_bytes());
+        request.extend((cookie.len() as u16).to_le_bytes());
+        request.extend([0, 0]);
+        push_padded(&mut request, name);
+        push_padded(&mut request, cookie);
+        stream.write_all(&request)?;
+
+        let mut header = [0; 8];
+        stream.read_exact(&mut header)?;
+        let mut data = vec![0; usize::from(u16::from_le_bytes([header[6], header[7]])) * 4];
+        stream.read_exact(&mut data)?;
+        match header[0] {
+            1 => {}
+            0 => {
+                let reason = data.get(..usize::from(header[1])).unwrap_or_default();
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!(
+                        "X server refused connection: {}",
+                        String::from_utf8_lossy(reason)
+                    ),
+                ));
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "X server requires further authentication",
+                ));
+            }
+        }
+
+        let (id_base, id_mask, root) = parse_setup(&data, screen).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidData, "malformed X connection setup")
+        })?;
+        Ok(Self {
+            stream,
+            id_base,
+            id_mask,
+            root,
+        })
+    }
+
+    fn request(&mut self, opcode: u8, data: u8, body: &[u8]) -> io::Result<()> {
+        let mut request = vec![opcode, data];
+        request.extend((((body.len().next_multiple_of(4) + 4) / 4) as u16).to_le_bytes());
+        push_padded(&mut request, body);
+        self.stream.write_all(&request)
+    }
+
+    /// Read until a reply arrives, skipping events.
+    fn reply(&mut self) -> io::Result<[u8; 32]> {
+        loop {
+            let packet = self.packet()?;
+            if packet[0] == 1 {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Read one error, reply or event; errors are returned as such.
+    fn packet(&mut self) -> io::Result<[u8; 32]> {
+        let mut packet = [0; 32];
+        self.stream.read_exact(&mut packet)?;
+        match packet[0] {
+            0 => Err(io::Error::other(format!(
+                "X request {} failed with error {}",
+                packet[10], packet[1]
+            ))),
+            1 => {
+                // Replies longer than 32 bytes carry their extra length
+                let extra = u32::from_le_bytes(packet[4..8].try_into().unwrap()) as u64 * 4;
+                io::copy(&mut (&mut self.stream).take(extra), &mut io::sink())?;
+                Ok(packet)
+            }
+            _ => Ok(packet),
+        }
+    }
+
+    fn intern_atom(&mut self, name: &str) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend((name.len() as u16).to_le_bytes());
+        body.extend([0, 0]);
+        body.extend(name.as_bytes());
+        self.request(INTERN_ATOM, 0, &body)?;
+        let reply = self.reply()?;
+        Ok(u32::from_le_bytes(reply[8..12].try_into().unwrap()))
+    }
+
+    /// An unmapped 1x1 input-only window that reports property changes.
+    fn create_window(&mut self) -> io::Result<u32> {
+        let window = self.id_base | (self.id_mask & self.id_mask.wrapping_neg());
+        let mut body = Vec::new();
+        body.extend(window.to_le_bytes());
+        body.extend(self.root.to_le_bytes());
+        body.extend([0; 4]); // x, y
+        body.extend([1, 0, 1, 0]); // width, height
+        body.extend([0, 0]); // border width
+        body.extend(INPUT_ONLY.to_le_bytes());
+        body.extend(0u32.to_le_bytes()); // visual: CopyFromParent
+        body.extend(CW_EVENT_MASK.to_le_bytes());
+        body.extend(PROPERTY_CHANGE_MASK.to_le_bytes());
+        self.request(CREATE_WINDOW, 0, &body)?;
+        Ok(window)
+    }
+
+    /// The server time, from the `PropertyNotify` of an empty property append.
+    fn server_time(&mut self, window: u32) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend(window.to_le_bytes());
+        body.extend(ATOM_WM_NAME.to_le_bytes());
+        body.extend(ATOM_STRING.to_le_bytes());
+        body.extend([8, 0, 0, 0]); // format
+        body.extend(0u32.to_le_bytes()); // data length
+        self.request(CHANGE_PROPERTY, PROP_MODE_APPEND, &body)?;
+        loop {
+            let packet = self.packet()?;
+            if packet[0] & 0x7f == PROPERTY_NOTIFY && packet[4..8] == window.to_le_bytes() {
+                return Ok(u32::from_le_bytes(packet[12..16].try_into().unwrap()));
+            }
+        }
+    }
+
+    /// Send a 32-byte event to the root window's property listeners.
+    fn send_event(&mut self, event: &[u8]) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.extend(self.root.to_le_bytes());
+        body.extend(PROPERTY_CHANGE_MASK.to_le_bytes());
+        body.extend(event);
+        self.request(SEND_EVENT, 0, &body)
+    }
+
+    /// Wait until the server has processed every request sent so far.
+    fn sync(&mut self) -> io::Result<()> {
+        self.request(GET_INPUT_FOCUS, 0, &[])?;
+        self.reply().map(drop)
+    }
+}
+
+fn push_padded(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend(data);
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, message)
+}
+
+/// The resource ID base and mask and the root window of `screen`, from the
+/// setup data following the 8-byte reply header.
+fn parse_setup(data: &[u8], screen: usize) -> Option<(u32, u32, u32)> {
+    let u16_at = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?));
+    let u32_at = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+    let id_base = u32_at(4)?;
+    let id_mask = u32_at(8)?;
+    let vendor_len = usize::from(u16_at(16)?);
+    let screens = usize::from(*data.get(20)?);
+    let formats = usize::from(*data.get(21)?);
+    if screen >= screens {
+        return None;
+    }
+
+    let mut offset = 32 + vendor_len.next_multiple_of(4) + formats * 8;
+    for _ in 0..screen {
+        let depths = *data.get(offset + 39)?;
+        offset += 40;
+        for _ in 0..depths {
+            offset += 8 + usize::from(u16_at(offset + 2)?) * 24;
+        }
+    }
+    Some((id_base, id_mask, u32_at(offset)?))
+}
+
+/// Display number and screen of a local `DISPLAY` (`:0`, `unix:0.1`).
+fn parse_display(display: &str) -> Option<(u32, usize)> {
+    let (host, rest) = display.rsplit_once(':')?;
+    if !host.is_empty() && host != "unix" {
+        return None;
+    }
+    let (number, screen) = rest.split_once('.').unwrap_or((rest, "0"));
+    Some((number.parse().ok()?, screen.parse().ok()?))
+}
+
+/// Connect to the abstract socket of display `number`, or to its socket file.
+fn connect(number: u32) -> io::Result<UnixStream> {
+    let path = format!("/tmp/.X11-unix/X{number}");
+    SocketAddr::from_abstract_name(path.as_bytes())
+        .and_then(|addr| UnixStream::connect_addr(&addr))
+        .or_else(|_| UnixStream::connect(&path))
+}
+
+fn xauthority() -> Option<PathBuf> {
+    std::env::var_os("XAUTHORITY")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".Xauthority")))
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// The `MIT-MAGIC-COOKIE-1` for display `number` on `host` in Xauthority `data`.
+fn cookie(data: &[u8], host: &[u8], number: &str) -> Option<Vec<u8>> {
+    let mut rest = data;
+    let mut take = |len: usize| -> Option<&[u8]> {
+        let (head, tail) = rest.split_at_checked(len)?;
+        rest = tail;
+        Some(head)
+    };
+    loop {
+        let family = u16::from_be_bytes(take(2)?.try_into().ok()?);
+        let mut field = || -> Option<&[u8]> {
+            let len = u16::from_be_bytes(take(2)?.try_into().ok()?);
+            take(usize::from(len))
+        };
+        let (address, display, name, cookie) = (field()?, field()?, field()?, field()?);
+        let host_matches = family == FAMILY_WILD || (family == FAMILY_LOCAL && address == host);
+        let display_matches = display.is_empty() || display == number.as_bytes();
+        if host_matches && display_matches && name == AUTH_NAME {
+            return Some(cookie.to_vec());
+        }
+    }
+}
+
+/// A startup-notification message, with every value quoted, and its terminating nul.
+fn message(kind: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut message = format!("{kind}:");
+    for (key, value) in fields {
+        let value = value.replace('\\', r"\\").replace('"', "\\\"");
+        message.push_str(&format!(" {key}=\"{value}\""));
+    }
+    let mut message = message.into_bytes();
+    message.push(0);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_parse_display() {
+        assert_eq!(parse_display(":0"), Some((0, 0)));
+        assert_eq!(parse_display("unix:1.2"), Some((1, 2)));
+        assert_eq!(parse_display("localhost:10.0"), None);
+        assert_eq!(parse_display(":x"), None);
+        assert_eq!(parse_display(""), None);
+    }
+
+    #[test]
+    fn test_cookie() {
+        let entry = |family: u16, address: &[u8], number: &[u8], name: &[u8], data: &[u8]| {
+            let mut out = family.to_be_bytes().to_vec();
+            for field in [address, number, name, data] {
+                out.extend((field.len() as u16).to_be_bytes());
+                out.extend(field);
+            }
+            out
+        };
+        let mut data = entry(FAMILY_LOCAL, b"other", b"0", AUTH_NAME, b"wrong host");
+        data.extend(entry(
+            FAMILY_LOCAL,
+            b"box",
+            b"1",
+            AUTH_NAME,
+            b"wrong display",
+        ));
+        data.extend(entry(
+            FAMILY_LOCAL,
+            b"box",
+            b"0",
+            b"XDM-AUTHORIZATION-1",
+            b"other",
+        ));
+        data.extend(entry(FAMILY_LOCAL, b"box", b"0", AUTH_NAME, b"secret"));
+        assert_eq!(cookie(&data, b"box", "0"), Some(b"secret".to_vec()));
+        assert_eq!(cookie(&data, b"box", "2"), None);
+
+        let wild = entry(FAMILY_WILD, b"", b"", AUTH_NAME, b"any");
+        assert_eq!(cookie(&wild, b"box", "2"), Some(b"any".to_vec()));
+        // Truncated files end the search
+        assert_eq!(cookie(&data[..data.len() - 1], b"box", "0"), None);
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(
+            message("new", &[("ID", "a_TIME5"), ("NAME", r#"say "hi" \"#)]),
+            b"new: ID=\"a_TIME5\" NAME=\"say \\\"hi\\\" \\\\\"\0"
+        );
+    }
+
+    /// Read one request from the client: its opcode and body, or `None` once
+    /// the client has disconnected.
+    fn read_request(stream: &mut UnixStream) -> Option<(u8, Vec<u8>)> {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).ok()?;
+        let len = usize::from(u16::from_le_bytes([header[2], header[3]])) * 4;
+        let mut body = vec![0; len - 4];
+        stream.read_exact(&mut body).unwrap();
+        Some((header[0], body))
+    }
+
+    fn reply(words: &[(usize, u32)]) -> [u8; 32] {
+        let mut reply = [0; 32];
+        reply[0] = 1;
+        for &(at, value) in words {
+            reply[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        reply
+    }
+
+    /// A fake X server with one screen whose root window is 0x100.
+    fn serve(mut stream: UnixStream) -> Vec<u8> {
+        let mut setup = [0; 12];
+        stream.read_exact(&mut setup).unwrap();
+        assert_eq!(&setup[..4], &[b'l', 0, 11, 0]);
+        let name_len = usize::from(u16::from_le_bytes([setup[6], setup[7]]));
+        let data_len = usize::from(u16::from_le_bytes([setup[8], setup[9]]));
+        let mut auth = vec![0; name_len.next_multiple_of(4) + data_len.next_multiple_of(4)];
+        stream.read_exact(&mut auth).unwrap();
+        assert_eq!(&auth[..name_len], AUTH_NAME);
+        assert_eq!(&auth[20..20 + data_len], b"secret");
+
+        // Vendor "fake", no formats, one screen
+        let mut data = vec![0; 32];
+        data[4..8].copy_from_slice(&0x0040_0000u32.to_le_bytes());
+        data[8..12].copy_from_slice(&0x001f_ffffu32.to_le_bytes());
+        data[16..18].copy_from_slice(&4u16.to_le_bytes());
+        data[20] = 1;
+        data.extend(b"fake");
+        let mut screen = [0; 40];
+        screen[..4].copy_from_slice(&0x100u32.to_le_bytes());
+        data.extend(screen);
+        let mut header = vec![1, 0, 11, 0, 0, 0];
+        header.extend(((data.len() / 4) as u16).to_le_bytes());
+        stream.write_all(&header).unwrap();
+        stream.write_all(&data).unwrap();
+
+        let mut messages = Vec::new();
+        let mut atoms = 0x200;
+        while let Some((opcode, body)) = read_request(&mut stream) {
+            match opcode {
+                INTERN_ATOM => {
+                    atoms += 1;
+                    stream.write_all(&reply(&[(8, atoms)])).unwrap();
+                }
+                CREATE_WINDOW => {
+                    assert_eq!(&body[..4], &0x0040_0001u32.to_le_bytes());
+                    assert_eq!(&body[4..8], &0x100u32.to_le_bytes());
+                }
+                CHANGE_PROPERTY => {
+                    let mut event = [0; 32];
+                    event[0] = PROPERTY_NOTIFY;
+                    event[4..8].copy_from_slice(&body[..4]);
+                    event[12..16].copy_from_slice(&4242u32.to_le_bytes());
+                    stream.write_all(&event).unwrap();
+                }
+                SEND_EVENT => {
+                    assert_eq!(&body[..4], &0x100u32.to_le_bytes());
+                    assert_eq!(body[8], CLIENT_MESSAGE);
+                    let kind = u32::from_le_bytes(body[16..20].try_into().unwrap());
+                    // The first chunk of each message is _NET_STARTUP_INFO_BEGIN
+                    let last = messages.last().copied();
+                    assert_eq!(kind == 0x201, last.is_none_or(|b| b == 0));
+                    messages.extend(&body[20..40]);
+                }
+                GET_INPUT_FOCUS => stream.write_all(&reply(&[])).unwrap(),
+                other => panic!("unexpected request {other}"),
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn test_sequence() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || serve(server));
+
+        let sequence = Sequence::start_on(client, b"secret", 0, "qmpo", "box").unwrap();
+        let id = sequence.id().to_string();
+        assert!(id.starts_with(&format!("qmpo-{}-box-", std::process::id())));
+        assert!(id.ends_with("_TIME4242"));
+        sequence.remove().unwrap();
+
+        let sent = server.join().unwrap();
+        let sent: Vec<&[u8]> = sent
+            .split(|&b| b == 0)
+            .filter(|message| !message.is_empty())
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                format!(r#"new: ID="{id}" NAME="qmpo" SCREEN="0""#).as_bytes(),
+                format!(r#"remove: ID="{id}""#).as_bytes(),
+            ]
+        );
+    }
+}